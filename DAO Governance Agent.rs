@@ -3,29 +3,191 @@ use solana_program::{
     account_info::{AccountInfo, next_account_info},
     entrypoint,
     entrypoint::ProgramResult,
+    keccak,
+    instruction::{AccountMeta, Instruction},
+    log::sol_log_data,
     msg,
     program_error::ProgramError,
     pubkey::Pubkey,
     system_program,
-    program::invoke,
+    program::invoke_signed,
+    program_pack::Pack,
     system_instruction,
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
-use std::collections::{HashMap};
+use spl_token::state::Account as TokenAccount;
+use std::collections::{HashMap, HashSet};
+
+// Layout version written as the first byte of ProgramState. Bump it whenever a
+// stored struct changes and teach `load_program_state` to migrate the old layout.
+// Version 1 is the layout of the first release to carry the version byte, every
+// change made before that release ships as part of it.
+const STATE_VERSION: u8 = 1;
+
+// Share of the total voting power that must participate under QuorumMode::PowerFraction
+const QUORUM_POWER_FRACTION: f64 = 0.01;
+
+// Upper bound on voting options so every vote index fits in a u8
+const MAX_VOTING_OPTIONS: usize = 16;
+
+// Label length limit used when a config leaves `max_option_label_len` at 0
+const DEFAULT_MAX_OPTION_LABEL_LEN: u32 = 64;
+
+// Title and description length limits used when a config leaves `max_title_len`
+// or `max_description_len` at 0, so no single proposal can grow the shared state unbounded
+const DEFAULT_MAX_TITLE_LEN: u32 = 128;
+const DEFAULT_MAX_DESCRIPTION_LEN: u32 = 2_048;
+
+// Tag limits. A config leaving `max_tags_per_proposal` at 0 uses the default count
+const DEFAULT_MAX_TAGS_PER_PROPOSAL: u32 = 5;
+const MAX_TAG_LEN: usize = 32;
+
+// Longest accepted `eligible_voters` list of a committee proposal
+const MAX_ELIGIBLE_VOTERS: usize = 64;
+
+// Maximum number of proposal ids returned by a single listing query
+const MAX_PROPOSALS_PER_PAGE: u32 = 50;
+
+// Maximum number of voting power records returned by a single GetDelegationGraph query
+const MAX_DELEGATIONS_PER_PAGE: u32 = 50;
+
+// Seed of the program owned treasury PDA that pays out proposal transfers
+const TREASURY_SEED: &[u8] = b"treasury";
+
+// Highest protocol fee a config may take from executed transfers, 10%
+const MAX_FEE_BASIS_POINTS: u16 = 1_000;
+
+// What an executed proposal does with the treasury
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq)]
+pub enum ProposalAction {
+    Transfer { to: Pubkey, lamports: u64 },
+    TransferFraction { to: Pubkey, basis_points: u16 }, // share of the treasury balance at execution time, clamped to 10000
+    GenericCpi { program_id: Pubkey, accounts_meta: Vec<CpiAccountMeta>, data: Vec<u8> }, // program_id must be whitelisted by the config
+    SetVotingPower { voter: Pubkey, power: u64 }, // governance controlled UpdateVotingPower, no treasury involved
+}
+
+// Borsh friendly AccountMeta for GenericCpi
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq)]
+pub struct CpiAccountMeta {
+    pub pubkey: Pubkey,
+    pub is_signer: bool, // only the treasury PDA is signed for, any other signer must sign the transaction
+    pub is_writable: bool,
+}
 
 // Proposal State
 #[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default)]
 pub struct Proposal {
     pub id: u32,
+    pub agent_id: u32, // Agent config whose governance rules apply to this proposal
     pub proposer: Pubkey,
     pub title: String,
     pub description: String,
     pub start_time: u64,
     pub end_time: u64,
+    pub end_slot: u64, // Last slot of voting, must be ahead of the current slot at creation. Voting closes once end_time and end_slot have passed
     pub voting_options: Vec<String>,  // Example: ["Yes", "No", "Abstain"]
     pub votes: HashMap<Pubkey, u8>, // Voter Pubkey => Vote Index (0,1,2 from voting options)
+    pub vote_weights: HashMap<Pubkey, u64>, // Voter Pubkey => Voting power captured when the vote was cast
     pub executed: bool,
-     pub target_account: Option<Pubkey>, // Account for a system transfer
+     pub target_account: Option<Pubkey>, // Account for a system transfer, superseded by `action`
       pub transfer_lamports: Option<u64>,
+    pub action: Option<ProposalAction>, // Treasury action run on execution, takes precedence over target_account/transfer_lamports
+    pub tags: Vec<String>, // Categories such as "treasury", "governance" or "social", set at creation
+    pub pass_index: u8, // Voting option counted towards the pass threshold, 0 by default
+    pub first_vote_order: Vec<u8>, // Options in the order they received their first vote, used by TieBreak::EarliestProposed
+    pub execution_result: Option<ExecutionResult>, // Set by ExecuteProposal
+    pub expired: bool, // Set by SweepExpiredProposals once voting ended without quorum, can't be executed afterwards
+    pub requires_instance: Option<u32>, // Agent instance that must have completed (status 2) before the proposal can be created
+    pub extended: bool, // Voting was extended by the config's grace_extension after quorum was missed
+    pub eligible_voters: Option<Vec<Pubkey>>, // Committee allowed to vote, None lets every power holder vote
+    pub cached_outcome: Option<ProposalOutcome>, // Tally computed once voting closed, cleared when voting is extended
+}
+
+// Tally of a closed proposal, cached so repeated ExecuteProposal attempts don't recount the votes
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq)]
+pub struct ProposalOutcome {
+    pub passed: bool,
+    pub quorum_met: bool,
+    pub winning_option: Option<u8>,
+    pub option_power: Vec<u64>, // weighted power per voting option
+}
+
+// Audit record of what ExecuteProposal actually did
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq)]
+pub struct ExecutionResult {
+    pub actions: Vec<ProposalAction>, // actions as run, a TransferFraction is recorded as the Transfer it resolved to, a fee as its own Transfer
+    pub timestamp: u64,
+    pub executor: Option<Pubkey>,     // first signer passed to ExecuteProposal, None if nobody signed
+}
+
+// Final tally emitted via sol_log_data whenever ExecuteProposal settles a proposal,
+// including when it fails on quorum or threshold
+#[derive(BorshSerialize, Debug)]
+pub struct ProposalFinalized {
+    pub proposal_id: u32,
+    pub option_power: Vec<u64>, // weighted power per voting option
+    pub total_power: u64,       // total participating power
+    pub quorum_met: bool,
+    pub passed: bool,
+    pub winning_option: Option<u8>,
+}
+
+// Outcome of a proposal reported by GetProposalResult
+#[derive(BorshSerialize, Debug)]
+pub struct ProposalResult {
+    pub proposal_id: u32,
+    pub executed: bool,
+    pub expired: bool,
+    pub quorum_met: bool, // as of the current votes, final once voting has ended
+    pub passed: bool,
+    pub execution_result: Option<ExecutionResult>,
+}
+
+// Per-option tally reported by GetVoteBreakdown
+#[derive(BorshSerialize, Debug)]
+pub struct VoteBreakdown {
+    pub proposal_id: u32,
+    pub options: Vec<(u32, u64)>, // (vote count, weighted power) per voting option
+    pub total_votes: u32,
+    pub total_power: u64,
+}
+
+// Lifecycle stage of a proposal, reported by GetProposalsByProposer
+#[derive(BorshSerialize, Debug, Clone, PartialEq)]
+pub enum ProposalStatus {
+    Pending,  // before start_time
+    Active,   // open for voting
+    Closed,   // voting closed, not executed yet
+    Executed,
+    Expired,
+}
+
+// Size of the program state, reported by GetStateStats
+#[derive(BorshSerialize, Debug)]
+pub struct StateStats {
+    pub agent_count: u32,
+    pub instance_count: u32,
+    pub proposal_count: u32,
+    pub voter_count: u32,      // records of the voting_power map
+    pub serialized_size: u64,  // bytes the state serializes to
+    pub allocated_size: u64,   // bytes allocated for the state account
+}
+
+// Quorum progress reported by GetQuorumProgress
+#[derive(BorshSerialize, Debug)]
+pub struct QuorumProgress {
+    pub proposal_id: u32,
+    pub participating: u64,      // participating power, or distinct voters under QuorumMode::VoterCount
+    pub quorum_target: u64,      // absolute amount `participating` has to reach
+    pub remaining_fraction: f64, // share of the target still missing, 0 once quorum is met
+}
+
+// Outcome of one create_proposal check, reported by SimulateProposal
+#[derive(BorshSerialize, Debug)]
+pub struct ProposalCheck {
+    pub name: String,
+    pub passed: bool,
+    pub error: Option<u64>, // ProgramError code of a failed check
 }
 
 // Voting Power Data
@@ -33,37 +195,180 @@ pub struct Proposal {
 pub struct VotingPower {
   pub voter: Pubkey,
   pub voting_power: u64,
-  pub delegated_to: Option<Pubkey>
+  pub delegated_to: Option<Pubkey>,
+  pub last_vote_time: u64, // Reset on every vote and power assignment, starts when the record is created. 0 (migrated records) never decays
 }
 
 // Agent Configuration for DAO
-#[derive(BorshDeserialize, BorshSerialize, Debug, Clone)]
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default)]
 pub struct AgentConfig {
     pub owner: Pubkey,
     pub description: String,
      pub voting_threshold: f64,  // percentage required for the proposal to pass, eg: 0.6
      pub quorum_threshold: f64, // percentage required to start a proposal
+    pub quorum_mode: QuorumMode,
+    pub min_voter_count: u32, // distinct voters required under QuorumMode::VoterCount
+    pub voting_power_source: VotingPowerSource,
+    pub governance_mint: Option<Pubkey>, // mint whose balance is the voting power under VotingPowerSource::TokenBalance
+    pub max_option_label_len: u32, // longest accepted voting option label in bytes, 0 uses DEFAULT_MAX_OPTION_LABEL_LEN
+    pub max_tags_per_proposal: u32, // 0 uses DEFAULT_MAX_TAGS_PER_PROPOSAL
+    pub voting_power_decay: VotingPowerDecay,
+    pub cpi_program_whitelist: Vec<Pubkey>, // programs a GenericCpi proposal action may invoke
+    pub max_total_voting_power: Option<u64>, // cap on the summed `voting_power` map, e.g. the token supply
+    pub tie_break: TieBreak,
+    pub min_voting_duration: u64, // bounds for CreateProposalWithDuration, in seconds
+    pub max_voting_duration: u64, // 0 leaves the duration unbounded
+    pub max_open_proposals: u32, // cap on this agent's proposals still open for voting, 0 disables the cap
+    // Early pass: with the flag set, a proposal whose passing option already holds more than
+    // `early_pass_threshold` of the total voting power can execute before `end_time`. This
+    // trades deliberation time for speed, late voters and vote changes no longer count.
+    pub early_pass_enabled: bool,
+    pub early_pass_threshold: f64,
+    pub abstain_mode: AbstainMode,
+    // Circulating quorum: with the flag set the PowerFraction quorum is measured against
+    // self-held power plus power delegated to addresses that have voted on some proposal,
+    // so parked delegations no longer make quorum unreachable. The flip side is that a
+    // small active set can reach quorum on its own, and parked power counts again as soon
+    // as its delegate casts any vote.
+    pub circulating_quorum: bool,
+    // Multi-sig ownership: with `owners` set, privileged instructions need signatures from
+    // `owner_threshold` of them and `owner` is no longer consulted. Empty keeps `owner` as 1-of-1.
+    pub owners: Vec<Pubkey>,
+    pub owner_threshold: u8,
+    pub fee_basis_points: u16, // Protocol fee taken out of every executed treasury transfer, 0 disables it
+    pub fee_account: Pubkey,   // Receives the fee
+    pub proposal_mint: Option<Pubkey>, // Token or NFT mint proposers must hold to create proposals, None lets anyone propose
+    pub min_proposal_balance: u64,     // Balance of `proposal_mint` required, at least 1
+    pub grace_extension: u64, // Seconds voting is extended, once, when quorum is missed at end_time. 0 fails right away
+    pub execution_permission: ExecutionPermission,
+    pub max_concurrent_instances: u32, // Instances with status created or running allowed per agent, 0 means no limit
+    pub require_voting_power: bool, // Refuse new proposals while the `voting_power` map sums to zero, e.g. on a fresh DAO
+    pub max_title_len: u32,       // longest accepted proposal title in bytes, 0 uses DEFAULT_MAX_TITLE_LEN
+    pub max_description_len: u32, // longest accepted proposal description in bytes, 0 uses DEFAULT_MAX_DESCRIPTION_LEN
+    pub grace_extension_slots: u64, // Slots the grace extension adds to end_slot alongside `grace_extension`
     // Add more DAO specific configs
 }
 
+// How quorum is measured when a proposal is executed
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default, PartialEq)]
+pub enum QuorumMode {
+    #[default]
+    PowerFraction, // participating power must reach 1% of the total voting power
+    VoterCount,    // at least `min_voter_count` distinct voters must take part
+}
+
+// Where a voter's power comes from when a vote is cast
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default, PartialEq)]
+pub enum VotingPowerSource {
+    #[default]
+    Manual,       // the `voting_power` map maintained through UpdateVotingPower
+    TokenBalance, // the voter's balance of `governance_mint`, read from a token account passed in
+}
+
+// How a tie between the options with the most weighted power is settled
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default, PartialEq)]
+pub enum TieBreak {
+    #[default]
+    LowestIndex,      // the option listed first in `voting_options` wins
+    Fail,             // a tie has no winner and the proposal does not pass
+    EarliestProposed, // the tied option that received its first vote earliest wins
+}
+
+// Who may call ExecuteProposal on a passed proposal
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default, PartialEq)]
+pub enum ExecutionPermission {
+    #[default]
+    Anyone,       // permissionless, any account can trigger a passed proposal
+    ProposerOnly, // the proposal's proposer must sign
+    OwnerOnly,    // the agent owner (or its multi-sig owners) must sign
+}
+
+// How votes for the "Abstain" option (matched case-insensitively) count at execution
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default, PartialEq)]
+pub enum AbstainMode {
+    Ignore,             // left out of both the quorum and the pass ratio
+    #[default]
+    CountAgainst,       // counted for quorum and in the pass ratio's denominator, raising the bar for yes
+    CountForQuorumOnly, // counted for quorum, left out of the pass ratio
+}
+
+// Opt-in decay of a holder's power while they don't vote. Under Linear the power is
+// untouched for `grace_period` seconds after the last vote and then falls to zero
+// over the following `decay_period` seconds:
+//   decayed = power * (decay_period - min(elapsed - grace_period, decay_period)) / decay_period
+// A fully decayed holder can't vote until UpdateVotingPower (or a SetVotingPower
+// proposal) reassigns their power, which restarts the clock.
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default, PartialEq)]
+pub enum VotingPowerDecay {
+    #[default]
+    None,
+    Linear { grace_period: u64, decay_period: u64 },
+}
+
+// Program specific errors, surfaced as ProgramError::Custom(code)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DaoError {
+    TooFewVotingOptions = 0,
+    TooManyVotingOptions = 1,
+    EmptyOptionLabel = 2,
+    DuplicateOptionLabel = 3,
+    OptionLabelTooLong = 4,
+    TooManyTags = 5,
+    InvalidTag = 6,
+    InvalidPassIndex = 7,
+    ProgramNotWhitelisted = 8,
+    MissingCpiAccount = 9,
+    CpiAccountMismatch = 10,
+    VotingPowerCapExceeded = 11,
+    InvalidVotingPeriod = 12,
+    InsufficientProposerPower = 13,
+    InvalidVotingDuration = 14,
+    TooManyOpenProposals = 15,
+    InvalidTransferTarget = 16,
+    InstanceRequirementUnmet = 17,
+    Unauthorized = 18,
+    NoVotingPower = 19,
+    VotingPowerDelegated = 20,
+    Ineligible = 21,             // voter is not on the proposal's eligible_voters list
+    InvalidEligibleVoters = 22,
+    TitleTooLong = 23,
+    DescriptionTooLong = 24,
+    StaleNonce = 25,             // VoteOnProposalWithNonce nonce not above the voter's last accepted one on the proposal, e.g. a replayed transaction
+    PrefilledVotes = 26,         // a new proposal arrived with votes, weights or a first vote order already set
+}
+
+impl From<DaoError> for ProgramError {
+    fn from(error: DaoError) -> Self {
+        ProgramError::Custom(error as u32)
+    }
+}
+
 // Agent Instance Structure
 #[derive(BorshDeserialize, BorshSerialize, Debug, Clone)]
 pub struct AgentInstance {
+    pub id: u32,
     pub agent_id: u32,
     pub status: u8,         // 0: created, 1: running, 2: completed, 3: error
     pub start_time: u64,
+    pub last_heartbeat: u64, // Time of the worker's last Heartbeat, starts at start_time
 }
 
 // Program State (Account Data)
 #[derive(BorshDeserialize, BorshSerialize, Debug, Default)]
 pub struct ProgramState {
+    pub version: u8,
+    pub is_initialized: bool,
+    pub owner: Pubkey,             // Recorded once by Initialize, must sign CreateAgent
     pub next_agent_id: u32,
+    pub next_instance_id: u32,     // Counter for instance ids, ids are never reused once an instance is closed
      pub next_proposal_id: u32,
     pub agent_configs: Vec<AgentConfig>,
     pub agent_instances: Vec<AgentInstance>,
      pub proposals: Vec<Proposal>,
       pub voting_power: HashMap<Pubkey, VotingPower>,
       pub last_analysis_time: u64,
+    pub pending_owners: HashMap<u32, Pubkey>, // AgentId -> owner named by TransferAgentOwnership, awaiting acceptance
+    pub vote_nonces: HashMap<(u32, Pubkey), u64>, // (ProposalId, Voter) -> highest nonce accepted by VoteOnProposalWithNonce
 }
 
 // Define Instruction Enum
@@ -76,7 +381,29 @@ pub enum AgentInstruction {
      VoteOnProposal { proposal_id: u32, vote_index: u8},
      ExecuteProposal { proposal_id: u32},
      DelegateVotingPower { delegate_to: Pubkey },
-     UpdateVotingPower { voter: Pubkey, voting_power: u64 },
+     UpdateVotingPower { agent_id: u32, voter: Pubkey, voting_power: u64 }, // signed by the owner(s) of `agent_id`
+    Initialize { owner: Pubkey },
+    GetQuorumProgress { proposal_id: u32 },
+    Heartbeat { instance_id: u32 },
+    GetStaleInstances { max_age: u64 },
+    ListProposalsByTag { tag: String, offset: u32, limit: u32 },
+    GetAgentConfig { agent_id: u32 },
+    GetAgentInstance { instance_id: u32 },
+    ExportChecksum,
+    SimulateProposal(Proposal),
+    CreateProposalWithDuration { proposal: Proposal, duration_secs: u64, duration_slots: u64 },
+    TransferAgentOwnership { agent_id: u32, new_owner: Pubkey },
+    AcceptAgentOwnership { agent_id: u32 },
+    GetTreasuryBalance,
+    GetProposalResult { proposal_id: u32 },
+    SweepExpiredProposals,
+    GetDelegationGraph { offset: u32, limit: u32 },
+    GetVoteBreakdown { proposal_id: u32 },
+    GetActiveProposals { offset: u32, limit: u32 },
+    GetProposalsByProposer { proposer: Pubkey, offset: u32, limit: u32 },
+    GetStateStats,
+    CloseAgentInstance { instance_id: u32 },
+    VoteOnProposalWithNonce { proposal_id: u32, vote_index: u8, nonce: u64 }, // nonce must exceed the voter's last one on the proposal
 }
 
 // Entrypoint
@@ -94,20 +421,46 @@ pub fn process_instruction(
      let accounts_iter = &mut accounts.iter();
     let state_account = next_account_info(accounts_iter)?;
 
-    if !state_account.is_writable {
+    // Read-only queries don't write the state back and may pass it read-only
+    let read_only = matches!(
+        instruction,
+        AgentInstruction::ExportChecksum
+            | AgentInstruction::GetStateStats
+            | AgentInstruction::SimulateProposal(_)
+            | AgentInstruction::GetTreasuryBalance
+            | AgentInstruction::GetProposalResult { .. }
+            | AgentInstruction::GetDelegationGraph { .. }
+            | AgentInstruction::GetVoteBreakdown { .. }
+            | AgentInstruction::GetActiveProposals { .. }
+            | AgentInstruction::GetProposalsByProposer { .. }
+            | AgentInstruction::GetAgentConfig { .. }
+            | AgentInstruction::GetAgentInstance { .. }
+            | AgentInstruction::GetQuorumProgress { .. }
+            | AgentInstruction::GetStaleInstances { .. }
+            | AgentInstruction::ListProposalsByTag { .. }
+    );
+    if !read_only && !state_account.is_writable {
         msg!("Program state account is not writeable");
         return Err(ProgramError::InvalidArgument);
     }
     
     // Load Program state (if available) or create a new one if not initialized
-    let mut program_state = ProgramState::try_from_slice(&state_account.data.borrow())
-         .unwrap_or_default();
+    let mut program_state = load_program_state(&state_account.data.borrow())?;
 
 
     match instruction {
+        AgentInstruction::Initialize { owner } => {
+            msg!("Initializing program state...");
+            initialize(&mut program_state, owner, program_id, state_account, accounts)?;
+        }
+        // Everything else needs an initialized state
+        _ if !program_state.is_initialized => {
+            msg!("Program state is not initialized");
+            return Err(ProgramError::UninitializedAccount);
+        }
         AgentInstruction::CreateAgent(config) => {
             msg!("Creating agent config...");
-            create_agent(&mut program_state, config, program_id, state_account)?;
+            create_agent(&mut program_state, config, program_id, state_account, accounts)?;
         }
         AgentInstruction::CreateAgentInstance { agent_id } => {
             msg!("Creating agent instance...");
@@ -117,25 +470,123 @@ pub fn process_instruction(
             msg!("Updating agent instance status...");
              update_agent_instance_status(&mut program_state, agent_id, instance_id, status, state_account)?;
         }
+        AgentInstruction::CloseAgentInstance { instance_id } => {
+            msg!("Closing agent instance...");
+            close_agent_instance(&mut program_state, instance_id, accounts)?;
+        }
         AgentInstruction::CreateProposal(proposal) => {
            msg!("Creating new proposal...");
-           create_proposal(&mut program_state, proposal, state_account)?;
+           create_proposal(&mut program_state, proposal, program_id, state_account, accounts)?;
         }
         AgentInstruction::VoteOnProposal{proposal_id, vote_index} => {
             msg!("Voting on proposal...");
-           vote_on_proposal(&mut program_state, proposal_id, vote_index, state_account)?;
+           vote_on_proposal(&mut program_state, proposal_id, vote_index, None, accounts)?;
+        }
+        AgentInstruction::VoteOnProposalWithNonce { proposal_id, vote_index, nonce } => {
+            msg!("Voting on proposal with nonce...");
+            vote_on_proposal(&mut program_state, proposal_id, vote_index, Some(nonce), accounts)?;
         }
        AgentInstruction::ExecuteProposal{proposal_id} => {
             msg!("Executing proposal...");
-            execute_proposal(&mut program_state, proposal_id, state_account, program_id)?;
+            execute_proposal(&mut program_state, proposal_id, state_account, accounts, program_id)?;
         }
        AgentInstruction::DelegateVotingPower{delegate_to} => {
             msg!("Delegating voting power");
-             delegate_voting_power(&mut program_state, delegate_to, state_account)?;
+             delegate_voting_power(&mut program_state, delegate_to, accounts)?;
         }
-       AgentInstruction::UpdateVotingPower{voter, voting_power} => {
+       AgentInstruction::UpdateVotingPower{agent_id, voter, voting_power} => {
             msg!("Updating voting power");
-            update_voting_power(&mut program_state, voter, voting_power, state_account)?;
+            update_voting_power(&mut program_state, agent_id, voter, voting_power, accounts)?;
+        }
+        AgentInstruction::GetQuorumProgress { proposal_id } => {
+            msg!("Fetching quorum progress");
+            get_quorum_progress(&program_state, proposal_id)?;
+            return Ok(());
+        }
+        AgentInstruction::Heartbeat { instance_id } => {
+            msg!("Recording instance heartbeat...");
+            heartbeat(&mut program_state, instance_id, accounts)?;
+        }
+        AgentInstruction::GetStaleInstances { max_age } => {
+            msg!("Listing stale instances...");
+            get_stale_instances(&program_state, max_age)?;
+            return Ok(());
+        }
+        AgentInstruction::ListProposalsByTag { tag, offset, limit } => {
+            msg!("Listing proposals by tag...");
+            list_proposals_by_tag(&program_state, tag, offset, limit)?;
+            return Ok(());
+        }
+        AgentInstruction::GetAgentConfig { agent_id } => {
+            msg!("Fetching agent config...");
+            get_agent_config(&program_state, agent_id)?;
+            return Ok(());
+        }
+        AgentInstruction::GetAgentInstance { instance_id } => {
+            msg!("Fetching agent instance...");
+            get_agent_instance(&program_state, instance_id)?;
+            return Ok(());
+        }
+        AgentInstruction::ExportChecksum => {
+            msg!("Exporting state checksum...");
+            export_checksum(&program_state)?;
+            return Ok(());
+        }
+        AgentInstruction::GetStateStats => {
+            msg!("Fetching state stats...");
+            get_state_stats(&program_state, state_account)?;
+            return Ok(());
+        }
+        AgentInstruction::SimulateProposal(proposal) => {
+            msg!("Simulating proposal...");
+            simulate_proposal(&program_state, &proposal, program_id, accounts)?;
+            return Ok(());
+        }
+        AgentInstruction::CreateProposalWithDuration { proposal, duration_secs, duration_slots } => {
+           msg!("Creating new proposal...");
+           create_proposal_with_duration(&mut program_state, proposal, duration_secs, duration_slots, program_id, state_account, accounts)?;
+        }
+        AgentInstruction::TransferAgentOwnership { agent_id, new_owner } => {
+            msg!("Transferring agent ownership...");
+            transfer_agent_ownership(&mut program_state, agent_id, new_owner, accounts)?;
+        }
+        AgentInstruction::AcceptAgentOwnership { agent_id } => {
+            msg!("Accepting agent ownership...");
+            accept_agent_ownership(&mut program_state, agent_id, accounts)?;
+        }
+        AgentInstruction::GetTreasuryBalance => {
+            msg!("Fetching treasury balance...");
+            get_treasury_balance(accounts, program_id)?;
+            return Ok(());
+        }
+        AgentInstruction::GetProposalResult { proposal_id } => {
+            msg!("Fetching proposal result...");
+            get_proposal_result(&program_state, proposal_id)?;
+            return Ok(());
+        }
+        AgentInstruction::SweepExpiredProposals => {
+            msg!("Sweeping expired proposals...");
+            sweep_expired_proposals(&mut program_state)?;
+        }
+        AgentInstruction::GetDelegationGraph { offset, limit } => {
+            msg!("Fetching delegation graph...");
+            get_delegation_graph(&program_state, offset, limit)?;
+            return Ok(());
+        }
+        AgentInstruction::GetVoteBreakdown { proposal_id } => {
+            msg!("Fetching vote breakdown...");
+            get_vote_breakdown(&program_state, proposal_id)?;
+            return Ok(());
+        }
+        AgentInstruction::GetActiveProposals { offset, limit } => {
+            msg!("Fetching active proposals...");
+            get_active_proposals(&program_state, offset, limit)?;
+            return Ok(());
+        }
+        AgentInstruction::GetProposalsByProposer { proposer, offset, limit } => {
+            msg!("Listing proposals by proposer...");
+            get_proposals_by_proposer(&program_state, proposer, offset, limit)?;
+            return Ok(());
         }
     }
 
@@ -146,17 +597,63 @@ pub fn process_instruction(
 }
 
 // Instruction implementations
+// The owner must sign, it is the only key allowed to create agents afterwards
+fn initialize(
+    program_state: &mut ProgramState,
+    owner: Pubkey,
+    program_id: &Pubkey,
+    state_account: &AccountInfo,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    if state_account.owner != program_id {
+        msg!("Incorrect owner for program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if program_state.is_initialized {
+        msg!("Program state is already initialized");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+    if !accounts.iter().any(|account| account.is_signer && *account.key == owner) {
+        msg!("Owner signature missing");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    program_state.is_initialized = true;
+    program_state.owner = owner;
+    msg!("Initialized program state with owner: {:?}", owner);
+    Ok(())
+}
+
 fn create_agent(
     program_state: &mut ProgramState,
     config: AgentConfig,
     program_id: &Pubkey,
      state_account: &AccountInfo,
+    accounts: &[AccountInfo],
 ) -> ProgramResult {
     // Check if the signer is the owner of program
      if state_account.owner != program_id {
         msg!("Incorrect owner for program");
         return Err(ProgramError::IncorrectProgramId);
     }
+    if !accounts.iter().any(|account| account.is_signer && *account.key == program_state.owner) {
+        msg!("Program owner signature missing");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if !config.owners.is_empty() {
+        let distinct = config.owners.iter().enumerate().all(|(index, owner)| !config.owners[..index].contains(owner));
+        if !distinct || config.owner_threshold == 0 || config.owner_threshold as usize > config.owners.len() {
+            msg!("Owner threshold must be between 1 and the number of distinct owners");
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
+    if config.fee_basis_points > MAX_FEE_BASIS_POINTS {
+        msg!("Fee of {} basis points exceeds the maximum of {}", config.fee_basis_points, MAX_FEE_BASIS_POINTS);
+        return Err(ProgramError::InvalidArgument);
+    }
+    if config.fee_basis_points > 0 {
+        check_transfer_target(&config.fee_account, program_id)?;
+    }
     
     let config_id = program_state.next_agent_id;
     program_state.agent_configs.push(config.clone());
@@ -179,13 +676,28 @@ fn create_agent_instance(
         return Err(ProgramError::InvalidArgument);
     }
 
+    let config = &program_state.agent_configs[agent_id as usize];
+    if config.max_concurrent_instances > 0 {
+        let active_instances = program_state.agent_instances.iter()
+            .filter(|instance| instance.agent_id == agent_id && instance.status <= 1)
+            .count();
+        if active_instances >= config.max_concurrent_instances as usize {
+            msg!("Agent {} already has {} of {} allowed instances created or running", agent_id, active_instances, config.max_concurrent_instances);
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
+
+    let start_time = solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64;
     let new_instance = AgentInstance {
+        id: program_state.next_instance_id,
         agent_id,
         status: 0, // Created status
-        start_time: solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64,
+        start_time,
+        last_heartbeat: start_time,
     };
 
      program_state.agent_instances.push(new_instance);
+     program_state.next_instance_id += 1;
      msg!("Created agent instance with agent ID: {}", agent_id);
     Ok(())
 }
@@ -197,12 +709,9 @@ fn update_agent_instance_status(
     status: u8,
     _state_account: &AccountInfo,
 ) -> ProgramResult {
-    if program_state.agent_instances.len() <= instance_id as usize {
-        msg!("Agent instance not found");
-        return Err(ProgramError::InvalidArgument);
-    }
+    let instance_index = find_instance_index(program_state, instance_id)?;
 
-     let instance = program_state.agent_instances.get_mut(instance_id as usize).unwrap();
+     let instance = &mut program_state.agent_instances[instance_index];
      if instance.agent_id != agent_id {
         msg!("Incorrect agent ID for the requested instance");
         return Err(ProgramError::InvalidArgument)
@@ -213,188 +722,1905 @@ fn update_agent_instance_status(
      Ok(())
 }
 
-fn create_proposal(
+// Removes a completed or errored instance so the state account stops growing.
+// Instances live inside the state account today; if they move to their own
+// accounts this should also drain the instance account's lamports to the owner.
+fn close_agent_instance(
     program_state: &mut ProgramState,
-    proposal: Proposal,
-    _state_account: &AccountInfo,
+    instance_id: u32,
+    accounts: &[AccountInfo],
 ) -> ProgramResult {
-     let mut proposal = proposal.clone();
-     proposal.id = program_state.next_proposal_id;
-     program_state.proposals.push(proposal);
-      program_state.next_proposal_id += 1;
+    let instance_index = find_instance_index(program_state, instance_id)?;
+    let instance = &program_state.agent_instances[instance_index];
+
+    let config = &program_state.agent_configs[instance.agent_id as usize];
+    verify_owner(config, accounts)?;
+
+    // Only completed (2) or errored (3) instances can be closed
+    if instance.status != 2 && instance.status != 3 {
+        msg!("Cannot close instance {} with status {}", instance_id, instance.status);
+        return Err(ProgramError::InvalidArgument);
+    }
 
-    msg!("Created proposal with ID: {}", proposal.id);
+    program_state.agent_instances.remove(instance_index);
+    msg!("Closed agent instance with ID: {}", instance_id);
     Ok(())
 }
 
-fn vote_on_proposal(
+// First step of an ownership handoff: the current owner names the next owner,
+// who then has to accept. Naming another owner replaces a pending transfer.
+fn transfer_agent_ownership(
     program_state: &mut ProgramState,
-    proposal_id: u32,
-    vote_index: u8,
-    state_account: &AccountInfo,
+    agent_id: u32,
+    new_owner: Pubkey,
+    accounts: &[AccountInfo],
 ) -> ProgramResult {
-      if program_state.proposals.len() <= proposal_id as usize {
-        msg!("Proposal not found");
-         return Err(ProgramError::InvalidArgument);
-      }
+    // Check if agent exists
+    if program_state.agent_configs.len() <= agent_id as usize {
+        msg!("Agent not found");
+        return Err(ProgramError::InvalidArgument);
+    }
+    verify_owner(&program_state.agent_configs[agent_id as usize], accounts)?;
 
-     let proposal = program_state.proposals.get_mut(proposal_id as usize).unwrap();
+    program_state.pending_owners.insert(agent_id, new_owner);
+    msg!("Agent {} ownership offered to {:?}", agent_id, new_owner);
+    Ok(())
+}
 
-       // Check if the voting time frame is open
-      let current_time = solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64;
-        if current_time < proposal.start_time || current_time > proposal.end_time {
-            msg!("Voting is not open for this proposal.");
+// Second step: the pending owner signs to take over the agent
+fn accept_agent_ownership(
+    program_state: &mut ProgramState,
+    agent_id: u32,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let new_owner = match program_state.pending_owners.get(&agent_id) {
+        Some(new_owner) => *new_owner,
+        None => {
+            msg!("No ownership transfer pending for agent {}", agent_id);
             return Err(ProgramError::InvalidArgument);
-         }
-
-     let voter = state_account.key;
+        }
+    };
+    let new_owner_signed = accounts.iter().any(|account| account.is_signer && *account.key == new_owner);
+    if !new_owner_signed {
+        msg!("Pending owner signature missing");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
 
-      // Get the voter voting power
-      let mut voter_voting_power = 1;
-      let voting_power = program_state.voting_power.get(voter);
-      if let Some(voter_details) = voting_power{
-            // Get the voting power of the delegated to user if it exists
-            let delegate_to = voter_details.delegated_to;
-            if let Some(delegate) = delegate_to{
-               let delegate_voting_power = program_state.voting_power.get(&delegate);
-               if let Some(delegate_details) = delegate_voting_power {
-                    voter_voting_power = delegate_details.voting_power;
-                }else{
-                    voter_voting_power = voter_details.voting_power;
-                }
-           }else{
-                 voter_voting_power = voter_details.voting_power;
-           }
-      }
-     
-     // Process the vote only if the user has voting power
-     if voter_voting_power > 0 {
-         proposal.votes.insert(*voter, vote_index);
-     }
-    msg!("Vote recorded for proposal with ID: {}", proposal_id);
+    // The new owner takes over alone, a multi-sig owner set is dissolved
+    let config = &mut program_state.agent_configs[agent_id as usize];
+    config.owner = new_owner;
+    config.owners.clear();
+    config.owner_threshold = 0;
+    program_state.pending_owners.remove(&agent_id);
+    msg!("Agent {} is now owned by {:?}", agent_id, new_owner);
     Ok(())
 }
 
-
-fn execute_proposal(
+// Records that the off-chain worker running an instance is still making progress.
+// The agent owner operates its instances, so the heartbeat must be signed by it.
+fn heartbeat(
     program_state: &mut ProgramState,
-    proposal_id: u32,
-    _state_account: &AccountInfo,
-    program_id: &Pubkey,
+    instance_id: u32,
+    accounts: &[AccountInfo],
 ) -> ProgramResult {
-    if program_state.proposals.len() <= proposal_id as usize {
-        msg!("Proposal not found");
-         return Err(ProgramError::InvalidArgument);
-      }
+    let instance_index = find_instance_index(program_state, instance_id)?;
+    let config = &program_state.agent_configs[program_state.agent_instances[instance_index].agent_id as usize];
+    verify_owner(config, accounts)?;
 
-      let proposal = program_state.proposals.get_mut(proposal_id as usize).unwrap();
-      if proposal.executed {
-          msg!("Proposal has already been executed.");
-          return Err(ProgramError::InvalidArgument);
-      }
-
-       // Check if the voting time frame has elapsed
-      let current_time = solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64;
-        if current_time < proposal.end_time  {
-            msg!("Voting is still open for this proposal.");
-             return Err(ProgramError::InvalidArgument);
-         }
+    let current_time = solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64;
+    program_state.agent_instances[instance_index].last_heartbeat = current_time;
+    msg!("Heartbeat for instance {} at {}", instance_id, current_time);
+    Ok(())
+}
 
-     // Check Quorum and Thresholds
-     let (passed, quorum_met) = check_proposal_result(proposal, program_state);
+// Logs the ids of created or running instances without a heartbeat in the last `max_age` seconds
+fn get_stale_instances(program_state: &ProgramState, max_age: u64) -> ProgramResult {
+    let current_time = solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64;
+    let stale: Vec<u32> = program_state.agent_instances.iter()
+        .filter(|instance| instance.status < 2 && current_time.saturating_sub(instance.last_heartbeat) > max_age)
+        .map(|instance| instance.id)
+        .collect();
 
-       if !quorum_met {
-            msg!("Proposal failed: Quorum not met");
-           return Err(ProgramError::InvalidArgument)
-       }
+    msg!("Found {} stale instances", stale.len());
+    sol_log_data(&[&stale.try_to_vec()?]);
+    Ok(())
+}
 
-       if !passed {
-           msg!("Proposal failed: Vote threshold not met");
-           return Err(ProgramError::InvalidArgument)
+fn get_agent_config(program_state: &ProgramState, agent_id: u32) -> ProgramResult {
+    let config = match program_state.agent_configs.get(agent_id as usize) {
+        Some(config) => config,
+        None => {
+            msg!("Agent not found");
+            return Err(ProgramError::InvalidArgument);
         }
+    };
+    sol_log_data(&[&config.try_to_vec()?]);
+    Ok(())
+}
 
-    // Execute Proposal Logic - system transfer as an example
-      if proposal.target_account.is_some() && proposal.transfer_lamports.is_some() {
-          msg!("Executing proposal: Transferring lamports.");
-             let target_account = proposal.target_account.unwrap();
-            let transfer_lamports = proposal.transfer_lamports.unwrap();
-            invoke(
-                &system_instruction::transfer(
-                    &program_id,
-                    &target_account,
-                     transfer_lamports,
-                  ),
-                  &[]
-             )?;
-       }
-      proposal.executed = true;
-      msg!("Proposal Executed with ID: {}", proposal_id);
-      Ok(())
+fn get_agent_instance(program_state: &ProgramState, instance_id: u32) -> ProgramResult {
+    let instance_index = find_instance_index(program_state, instance_id)?;
+    sol_log_data(&[&program_state.agent_instances[instance_index].try_to_vec()?]);
+    Ok(())
 }
 
-fn delegate_voting_power(
-    program_state: &mut ProgramState,
-    delegate_to: Pubkey,
-      state_account: &AccountInfo,
-) -> ProgramResult {
+// Logs the keccak hash of the serialized state and its length so an off-chain
+// mirror can check that it serializes to exactly the same bytes
+fn export_checksum(program_state: &ProgramState) -> ProgramResult {
+    let serialized = program_state.try_to_vec()?;
+    let digest = keccak::hash(&serialized);
+    msg!("State checksum {} over {} bytes", digest, serialized.len());
+    sol_log_data(&[&digest.to_bytes(), &(serialized.len() as u64).to_le_bytes()]);
+    Ok(())
+}
 
-    let voter = state_account.key;
-    // Fetch the voter details and then update the voting power.
-    let voting_power = program_state.voting_power.get_mut(voter);
-    if let Some(voting_details) = voting_power{
-         voting_details.delegated_to = Some(delegate_to);
-    }else{
-        let new_voting_details = VotingPower{
-            voter: *voter,
-            voting_power: 1,
-            delegated_to: Some(delegate_to)
-        };
-        program_state.voting_power.insert(*voter, new_voting_details);
-    }
-      msg!("Voting power delegated from {:?} to {:?}", voter, delegate_to);
-        Ok(())
+// Logs entry counts and the serialized state size against the account's allocated
+// size, so operators can alert before the state outgrows its account
+fn get_state_stats(program_state: &ProgramState, state_account: &AccountInfo) -> ProgramResult {
+    let stats = StateStats {
+        agent_count: program_state.agent_configs.len() as u32,
+        instance_count: program_state.agent_instances.len() as u32,
+        proposal_count: program_state.proposals.len() as u32,
+        voter_count: program_state.voting_power.len() as u32,
+        serialized_size: program_state.try_to_vec()?.len() as u64,
+        allocated_size: state_account.data_len() as u64,
+    };
+    msg!("State uses {} of {} allocated bytes", stats.serialized_size, stats.allocated_size);
+    sol_log_data(&[&stats.try_to_vec()?]);
+    Ok(())
 }
 
-fn update_voting_power(
+fn create_proposal(
     program_state: &mut ProgramState,
-    voter: Pubkey,
-    voting_power: u64,
-     _state_account: &AccountInfo,
+    proposal: Proposal,
+    program_id: &Pubkey,
+    _state_account: &AccountInfo,
+    accounts: &[AccountInfo],
 ) -> ProgramResult {
+     for (_, result) in proposal_checks(program_state, &proposal, program_id, accounts) {
+         result?;
+     }
 
-      let voting_details = program_state.voting_power.get_mut(&voter);
+     // Vote and lifecycle state is only ever written by the program, whatever the client sent
+     let mut proposal = proposal.clone();
+     let proposal_id = program_state.next_proposal_id;
+     proposal.id = proposal_id;
+     proposal.votes.clear();
+     proposal.vote_weights.clear();
+     proposal.first_vote_order.clear();
+     proposal.executed = false;
+     proposal.expired = false;
+     proposal.extended = false;
+     proposal.execution_result = None;
+     proposal.cached_outcome = None;
+     program_state.proposals.push(proposal);
+      program_state.next_proposal_id += 1;
 
-        if let Some(voting_power_details) = voting_details {
-              voting_power_details.voting_power = voting_power;
-        }else{
-             let new_voting_details = VotingPower{
-                voter: voter,
-                voting_power: voting_power,
-                delegated_to: None
-            };
-             program_state.voting_power.insert(voter, new_voting_details);
-        }
-     msg!("Updated voting power of {:?} to {}", voter, voting_power);
+    msg!("Created proposal with ID: {}", proposal_id);
     Ok(())
 }
 
-fn check_proposal_result(proposal: &Proposal, program_state: &ProgramState) -> (bool, bool) {
-     // Get the total voting power available
-     let total_voting_power : u64 = program_state.voting_power.values().fold(0, |acc, x| acc + x.voting_power);
-
-    // Calculate Total number of votes
-      let total_voters = proposal.votes.len() as u64;
-      let quorum_met =  total_voters as f64 / total_voting_power as f64 >= 0.01;
-
-      if !quorum_met{
-        return (false, false);
-      }
-     
-      // Calculate the number of yes votes
-      let total_yes_votes = proposal.votes.values().filter(|&vote| *vote == 0).count();
-
-      let vote_threshold_met = total_yes_votes as f64 / total_voters as f64 >= 0.6;
-      
+// Opens voting now and closes it `duration_secs` and `duration_slots` later, so clients
+// don't have to derive timestamps or slots from their own clock
+fn create_proposal_with_duration(
+    program_state: &mut ProgramState,
+    mut proposal: Proposal,
+    duration_secs: u64,
+    duration_slots: u64,
+    program_id: &Pubkey,
+    state_account: &AccountInfo,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let config = match program_state.agent_configs.get(proposal.agent_id as usize) {
+        Some(config) => config,
+        None => {
+            msg!("Agent not found");
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+    let too_long = config.max_voting_duration != 0 && duration_secs > config.max_voting_duration;
+    if duration_secs == 0 || duration_secs < config.min_voting_duration || too_long {
+        msg!("Voting duration {} is outside {}..{}", duration_secs, config.min_voting_duration, config.max_voting_duration);
+        return Err(DaoError::InvalidVotingDuration.into());
+    }
+    if duration_slots == 0 {
+        msg!("Voting duration needs at least one slot");
+        return Err(DaoError::InvalidVotingDuration.into());
+    }
+
+    let clock = solana_program::sysvar::clock::Clock::get().unwrap();
+    proposal.start_time = clock.unix_timestamp as u64;
+    proposal.end_time = proposal.start_time.saturating_add(duration_secs);
+    proposal.end_slot = clock.slot.saturating_add(duration_slots);
+    create_proposal(program_state, proposal, program_id, state_account, accounts)
+}
+
+// Every validation create_proposal runs, with its outcome
+fn proposal_checks(
+    program_state: &ProgramState,
+    proposal: &Proposal,
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> Vec<(&'static str, ProgramResult)> {
+     // Check if the governing agent exists
+     let config = match program_state.agent_configs.get(proposal.agent_id as usize) {
+         Some(config) => config,
+         None => {
+             msg!("Agent not found");
+             return vec![("agent", Err(ProgramError::InvalidArgument))];
+         }
+     };
+     let clock = solana_program::sysvar::clock::Clock::get().unwrap();
+     let current_time = clock.unix_timestamp as u64;
+
+     vec![
+         ("no_votes", validate_no_votes(proposal)),
+         ("proposer_signature", validate_proposer_signature(proposal, accounts)),
+         ("voting_period", validate_voting_period(proposal, &clock)),
+         ("text", validate_text_lengths(proposal, config)),
+         ("voting_options", validate_voting_options(&proposal.voting_options, config)),
+         ("tags", validate_tags(&proposal.tags, config)),
+         ("pass_index", validate_pass_index(proposal)),
+         ("transfer_target", validate_transfer_target(proposal, program_id)),
+         ("required_instance", validate_required_instance(program_state, proposal)),
+         ("eligible_voters", validate_eligible_voters(proposal)),
+         ("voting_power_exists", validate_voting_power_exists(program_state, config)),
+         ("proposer_power", validate_proposer_power(program_state, proposal, config)),
+         ("proposer_token", validate_proposer_token(proposal, config, accounts)),
+         ("open_proposals", validate_open_proposals(program_state, proposal, config, current_time)),
+     ]
+}
+
+// Runs create_proposal's checks without writing state or using up a proposal id
+fn simulate_proposal(program_state: &ProgramState, proposal: &Proposal, program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let checks: Vec<ProposalCheck> = proposal_checks(program_state, proposal, program_id, accounts).into_iter()
+        .map(|(name, result)| ProposalCheck {
+            name: name.to_string(),
+            passed: result.is_ok(),
+            error: result.err().map(u64::from),
+        })
+        .collect();
+
+    msg!("{} of {} proposal checks passed", checks.iter().filter(|check| check.passed).count(), checks.len());
+    sol_log_data(&[&checks.try_to_vec()?]);
+    Ok(())
+}
+
+fn validate_no_votes(proposal: &Proposal) -> ProgramResult {
+    if !proposal.votes.is_empty() || !proposal.vote_weights.is_empty() || !proposal.first_vote_order.is_empty() {
+        msg!("A new proposal can't carry votes");
+        return Err(DaoError::PrefilledVotes.into());
+    }
+    Ok(())
+}
+
+fn validate_voting_period(proposal: &Proposal, clock: &Clock) -> ProgramResult {
+    let current_time = clock.unix_timestamp as u64;
+    if proposal.start_time < current_time {
+        msg!("Voting can't start in the past, start_time {} is before {}", proposal.start_time, current_time);
+        return Err(DaoError::InvalidVotingPeriod.into());
+    }
+    if proposal.end_time <= proposal.start_time {
+        msg!("Voting period {}..{} is empty", proposal.start_time, proposal.end_time);
+        return Err(DaoError::InvalidVotingPeriod.into());
+    }
+    if proposal.end_slot <= clock.slot {
+        msg!("Voting end_slot {} is not after the current slot {}", proposal.end_slot, clock.slot);
+        return Err(DaoError::InvalidVotingPeriod.into());
+    }
+    Ok(())
+}
+
+fn validate_pass_index(proposal: &Proposal) -> ProgramResult {
+    if proposal.pass_index as usize >= proposal.voting_options.len() {
+        msg!("Pass index {} is not one of the {} voting options", proposal.pass_index, proposal.voting_options.len());
+        return Err(DaoError::InvalidPassIndex.into());
+    }
+    Ok(())
+}
+
+// Transfers to the treasury itself would be a no-op, and the program id or the zero
+// pubkey can never be meaningful recipients
+fn validate_transfer_target(proposal: &Proposal, program_id: &Pubkey) -> ProgramResult {
+    let target = match &proposal.action {
+        Some(ProposalAction::Transfer { to, .. }) | Some(ProposalAction::TransferFraction { to, .. }) => Some(to),
+        Some(_) => None,
+        None => proposal.target_account.as_ref(),
+    };
+    match target {
+        Some(to) => check_transfer_target(to, program_id),
+        None => Ok(()),
+    }
+}
+
+fn check_transfer_target(to: &Pubkey, program_id: &Pubkey) -> ProgramResult {
+    let (treasury, _) = Pubkey::find_program_address(&[TREASURY_SEED], program_id);
+    let reason = if *to == treasury {
+        "the treasury itself"
+    } else if to == program_id {
+        "the program id"
+    } else if *to == Pubkey::default() {
+        "the zero pubkey"
+    } else {
+        return Ok(());
+    };
+    msg!("Transfer target {} is {}", to, reason);
+    Err(DaoError::InvalidTransferTarget.into())
+}
+
+fn validate_required_instance(program_state: &ProgramState, proposal: &Proposal) -> ProgramResult {
+    let instance_id = match proposal.requires_instance {
+        Some(instance_id) => instance_id,
+        None => return Ok(()),
+    };
+    match program_state.agent_instances.iter().find(|instance| instance.id == instance_id) {
+        Some(instance) if instance.status == 2 => Ok(()),
+        Some(instance) => {
+            msg!("Required agent instance {} has status {}, it must be completed (2)", instance_id, instance.status);
+            Err(DaoError::InstanceRequirementUnmet.into())
+        }
+        None => {
+            msg!("Required agent instance {} does not exist", instance_id);
+            Err(DaoError::InstanceRequirementUnmet.into())
+        }
+    }
+}
+
+// The proposer's power and tokens gate creation, so the proposer must sign or
+// anyone could name a large holder as `proposer`.
+// Accounts: [state, proposer (signer), proposer token account (token-gated agents only)]
+fn validate_proposer_signature(proposal: &Proposal, accounts: &[AccountInfo]) -> ProgramResult {
+    let proposer_account = next_account_info(&mut accounts.iter().skip(1))?;
+    if !proposer_account.is_signer || *proposer_account.key != proposal.proposer {
+        msg!("Proposer signature missing");
+        return Err(DaoError::Unauthorized.into());
+    }
+    Ok(())
+}
+
+// Token-gated agents only accept proposals from holders of `proposal_mint`, checked
+// independently of the proposer's voting power. validate_proposer_signature makes sure
+// another holder's token account can't be borrowed.
+fn validate_proposer_token(proposal: &Proposal, config: &AgentConfig, accounts: &[AccountInfo]) -> ProgramResult {
+    let mint = match config.proposal_mint {
+        Some(mint) => mint,
+        None => return Ok(()),
+    };
+    let accounts_iter = &mut accounts.iter().skip(2);
+    let token_account = next_account_info(accounts_iter)?;
+    if token_account.owner != &spl_token::id() {
+        msg!("Proposer token account is not owned by the token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let token = TokenAccount::unpack(&token_account.data.borrow())?;
+    let required = config.min_proposal_balance.max(1);
+    if token.mint != mint || token.owner != proposal.proposer || token.amount < required {
+        msg!("Proposer must hold at least {} of mint {}", required, mint);
+        return Err(DaoError::Unauthorized.into());
+    }
+    Ok(())
+}
+
+// A fresh DAO has an empty `voting_power` map, so no proposal could ever reach a
+// PowerFraction quorum. With `require_voting_power` set creation waits until some
+// power has been assigned instead of opening proposals that can only fail.
+fn validate_voting_power_exists(program_state: &ProgramState, config: &AgentConfig) -> ProgramResult {
+    if !config.require_voting_power {
+        return Ok(());
+    }
+    if program_state.voting_power.values().all(|details| details.voting_power == 0) {
+        msg!("No voting power has been assigned yet");
+        return Err(DaoError::NoVotingPower.into());
+    }
+    Ok(())
+}
+
+// The proposer needs `quorum_threshold` of the total voting power to start a
+// proposal. Token balance power can't be read without the proposer's token
+// account, so the check only applies to the Manual power source.
+fn validate_proposer_power(program_state: &ProgramState, proposal: &Proposal, config: &AgentConfig) -> ProgramResult {
+    if config.voting_power_source != VotingPowerSource::Manual || config.quorum_threshold <= 0.0 {
+        return Ok(());
+    }
+    let total_voting_power = saturating_sum(program_state.voting_power.values().map(|details| details.voting_power));
+    if total_voting_power == 0 {
+        return Ok(());
+    }
+
+    let proposer_power = effective_voting_power(program_state, &proposal.proposer);
+    if (proposer_power as f64 / total_voting_power as f64) < config.quorum_threshold {
+        msg!("Proposer holds {} of {} voting power, {} required", proposer_power, total_voting_power, config.quorum_threshold);
+        return Err(DaoError::InsufficientProposerPower.into());
+    }
+    Ok(())
+}
+
+fn validate_open_proposals(program_state: &ProgramState, proposal: &Proposal, config: &AgentConfig, current_time: u64) -> ProgramResult {
+    if config.max_open_proposals == 0 {
+        return Ok(());
+    }
+    let open_proposals = program_state.proposals.iter()
+        .filter(|open| open.agent_id == proposal.agent_id && !open.executed && open.end_time > current_time)
+        .count();
+    if open_proposals >= config.max_open_proposals as usize {
+        msg!("Agent already has {} open proposals, the limit is {}", open_proposals, config.max_open_proposals);
+        return Err(DaoError::TooManyOpenProposals.into());
+    }
+    Ok(())
+}
+
+fn validate_tags(tags: &[String], config: &AgentConfig) -> ProgramResult {
+    let max_tags = match config.max_tags_per_proposal {
+        0 => DEFAULT_MAX_TAGS_PER_PROPOSAL,
+        max_tags => max_tags,
+    } as usize;
+    if tags.len() > max_tags {
+        msg!("A proposal can have at most {} tags", max_tags);
+        return Err(DaoError::TooManyTags.into());
+    }
+
+    for (index, tag) in tags.iter().enumerate() {
+        if tag.trim().is_empty() || tag.len() > MAX_TAG_LEN || tags[..index].contains(tag) {
+            msg!("Tag '{}' must be unique, non-empty and at most {} bytes", tag, MAX_TAG_LEN);
+            return Err(DaoError::InvalidTag.into());
+        }
+    }
+    Ok(())
+}
+
+fn validate_text_lengths(proposal: &Proposal, config: &AgentConfig) -> ProgramResult {
+    let max_title_len = match config.max_title_len {
+        0 => DEFAULT_MAX_TITLE_LEN,
+        max_title_len => max_title_len,
+    } as usize;
+    if proposal.title.len() > max_title_len {
+        msg!("Title is {} bytes, at most {} are allowed", proposal.title.len(), max_title_len);
+        return Err(DaoError::TitleTooLong.into());
+    }
+
+    let max_description_len = match config.max_description_len {
+        0 => DEFAULT_MAX_DESCRIPTION_LEN,
+        max_description_len => max_description_len,
+    } as usize;
+    if proposal.description.len() > max_description_len {
+        msg!("Description is {} bytes, at most {} are allowed", proposal.description.len(), max_description_len);
+        return Err(DaoError::DescriptionTooLong.into());
+    }
+    Ok(())
+}
+
+fn validate_eligible_voters(proposal: &Proposal) -> ProgramResult {
+    let voters = match &proposal.eligible_voters {
+        Some(voters) => voters,
+        None => return Ok(()),
+    };
+    let distinct = voters.iter().enumerate().all(|(index, voter)| !voters[..index].contains(voter));
+    if voters.is_empty() || voters.len() > MAX_ELIGIBLE_VOTERS || !distinct {
+        msg!("Eligible voters must be 1 to {} distinct keys", MAX_ELIGIBLE_VOTERS);
+        return Err(DaoError::InvalidEligibleVoters.into());
+    }
+    Ok(())
+}
+
+fn validate_voting_options(voting_options: &[String], config: &AgentConfig) -> ProgramResult {
+    if voting_options.len() < 2 {
+        msg!("A proposal needs at least two voting options");
+        return Err(DaoError::TooFewVotingOptions.into());
+    }
+    if voting_options.len() > MAX_VOTING_OPTIONS {
+        msg!("A proposal can have at most {} voting options", MAX_VOTING_OPTIONS);
+        return Err(DaoError::TooManyVotingOptions.into());
+    }
+
+    let max_label_len = match config.max_option_label_len {
+        0 => DEFAULT_MAX_OPTION_LABEL_LEN,
+        max_label_len => max_label_len,
+    } as usize;
+    for (index, label) in voting_options.iter().enumerate() {
+        if label.trim().is_empty() {
+            msg!("Voting option {} has an empty label", index);
+            return Err(DaoError::EmptyOptionLabel.into());
+        }
+        if label.len() > max_label_len {
+            msg!("Voting option {} is longer than {} bytes", index, max_label_len);
+            return Err(DaoError::OptionLabelTooLong.into());
+        }
+        if voting_options[..index].contains(label) {
+            msg!("Voting option '{}' is listed more than once", label);
+            return Err(DaoError::DuplicateOptionLabel.into());
+        }
+    }
+
+    Ok(())
+}
+
+// A vote carrying a nonce is only accepted if the nonce is above the last one the voter
+// used, so a rebroadcast transaction can't be processed twice. Nonces are tracked per
+// voter, not per proposal, which keeps the state to one entry per voter.
+fn vote_on_proposal(
+    program_state: &mut ProgramState,
+    proposal_id: u32,
+    vote_index: u8,
+    nonce: Option<u64>,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+      if program_state.proposals.len() <= proposal_id as usize {
+        msg!("Proposal not found");
+         return Err(ProgramError::InvalidArgument);
+      }
+
+     let proposal = &program_state.proposals[proposal_id as usize];
+
+       // Check if the voting time frame is open
+      let clock = Clock::get()?;
+      let current_time = clock.unix_timestamp as u64;
+        if current_time < proposal.start_time || voting_closed(proposal, &clock) {
+            msg!("Voting is not open for this proposal.");
+            return Err(ProgramError::InvalidArgument);
+         }
+
+     // Accounts: [state, voter (signer), voter token account (TokenBalance mode only)]
+     let accounts_iter = &mut accounts.iter().skip(1);
+     let voter_account = next_account_info(accounts_iter)?;
+     if !voter_account.is_signer {
+        msg!("Voter signature missing");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+     let voter = voter_account.key;
+
+      // Nonces are tracked per proposal, a nonce used on one proposal says nothing about another
+      if let (Some(nonce), Some(last_nonce)) = (nonce, program_state.vote_nonces.get(&(proposal_id, *voter))) {
+          if nonce <= *last_nonce {
+              msg!("Nonce {} is not above the last accepted nonce {}", nonce, last_nonce);
+              return Err(DaoError::StaleNonce.into());
+          }
+      }
+
+      // Committee proposals only accept votes from their listed voters
+      if proposal.eligible_voters.as_ref().map_or(false, |voters| !voters.contains(voter)) {
+          msg!("{} is not an eligible voter for this proposal", voter);
+          return Err(DaoError::Ineligible.into());
+      }
+
+      // Power handed to a delegate is exercised by the delegate alone
+      if let Some(delegate) = program_state.voting_power.get(voter).and_then(|details| details.delegated_to) {
+          msg!("Voting power is delegated to {}", delegate);
+          return Err(DaoError::VotingPowerDelegated.into());
+      }
+
+      // Get the voter voting power
+      let config = &program_state.agent_configs[proposal.agent_id as usize];
+      let mut voter_voting_power = match config.voting_power_source {
+          VotingPowerSource::Manual => effective_voting_power(program_state, voter),
+          VotingPowerSource::TokenBalance => token_voting_power(config, voter, next_account_info(accounts_iter)?)?,
+      };
+      if voter_voting_power == 0 {
+          msg!("Voter has no voting power");
+          return Err(DaoError::NoVotingPower.into());
+      }
+      if let Some(voter_details) = program_state.voting_power.get(voter) {
+          voter_voting_power = decay_voting_power(&config.voting_power_decay, voter_voting_power, voter_details.last_vote_time, current_time);
+      }
+      if voter_voting_power == 0 {
+          msg!("Voting power has fully decayed, it has to be reassigned before voting again");
+          return Err(DaoError::NoVotingPower.into());
+      }
+
+     // A changed vote keeps the weight captured by the voter's first vote on the
+     // proposal, so voting again can't pick up power the decay already took
+     let proposal = &mut program_state.proposals[proposal_id as usize];
+     proposal.votes.insert(*voter, vote_index);
+     proposal.vote_weights.entry(*voter).or_insert(voter_voting_power);
+     if !proposal.first_vote_order.contains(&vote_index) {
+         proposal.first_vote_order.push(vote_index);
+     }
+     // The vote restarts the decay clock
+     if let Some(voter_details) = program_state.voting_power.get_mut(voter) {
+         voter_details.last_vote_time = current_time;
+     }
+     if let Some(nonce) = nonce {
+         program_state.vote_nonces.insert((proposal_id, *voter), nonce);
+     }
+    msg!("Vote recorded for proposal with ID: {}", proposal_id);
+    Ok(())
+}
+
+
+fn execute_proposal(
+    program_state: &mut ProgramState,
+    proposal_id: u32,
+    state_account: &AccountInfo,
+    accounts: &[AccountInfo],
+    program_id: &Pubkey,
+) -> ProgramResult {
+    if program_state.proposals.len() <= proposal_id as usize {
+        msg!("Proposal not found");
+         return Err(ProgramError::InvalidArgument);
+      }
+
+      let proposal = &program_state.proposals[proposal_id as usize];
+      if proposal.executed {
+          msg!("Proposal has already been executed.");
+          return Err(ProgramError::InvalidArgument);
+      }
+      if proposal.expired {
+          msg!("Proposal expired without reaching quorum.");
+          return Err(ProgramError::InvalidArgument);
+      }
+
+     let config = &program_state.agent_configs[proposal.agent_id as usize];
+     match config.execution_permission {
+         ExecutionPermission::Anyone => {}
+         ExecutionPermission::ProposerOnly => {
+             if !accounts.iter().any(|account| account.is_signer && *account.key == proposal.proposer) {
+                 msg!("Only the proposer may execute this proposal");
+                 return Err(ProgramError::MissingRequiredSignature);
+             }
+         }
+         ExecutionPermission::OwnerOnly => verify_owner(config, accounts)?,
+     }
+
+       // Check if the voting time frame has elapsed
+      let clock = Clock::get()?;
+      let current_time = clock.unix_timestamp as u64;
+        if !voting_closed(proposal, &clock) {
+            if !early_pass_reached(proposal, config, program_state) {
+                msg!("Voting is still open for this proposal.");
+                return Err(ProgramError::InvalidArgument);
+            }
+            msg!("Proposal passed early, executing before the end of voting.");
+         }
+
+     // Check Quorum and Thresholds. Once voting has closed the outcome is cached on the
+     // proposal and reused by later attempts, an early pass is still recomputed each time.
+     let closed = voting_closed(proposal, &clock);
+     let outcome = proposal_outcome(program_state, proposal_id, closed);
+     let (passed, quorum_met) = (outcome.passed, outcome.quorum_met);
+     let proposal = &program_state.proposals[proposal_id as usize];
+     let config = &program_state.agent_configs[proposal.agent_id as usize];
+
+       // A missed quorum first buys the proposal its one grace extension. The call succeeds
+       // so the new end_time is persisted, the proposal isn't finalized yet.
+       if !quorum_met && config.grace_extension > 0 && !proposal.extended && closed {
+           let (grace_extension, grace_extension_slots) = (config.grace_extension, config.grace_extension_slots);
+           extend_voting(&mut program_state.proposals[proposal_id as usize], grace_extension, grace_extension_slots, &clock);
+           return Ok(());
+       }
+
+       let finalized = ProposalFinalized {
+           proposal_id,
+           total_power: saturating_sum(outcome.option_power.iter().copied()),
+           winning_option: outcome.winning_option,
+           option_power: outcome.option_power,
+           quorum_met,
+           passed,
+       };
+       sol_log_data(&[&finalized.try_to_vec()?]);
+
+       if !quorum_met {
+            msg!("Proposal failed: Quorum not met");
+           return Err(ProgramError::InvalidArgument)
+       }
+
+       if !passed {
+           msg!("Proposal failed: Vote threshold not met");
+           return Err(ProgramError::InvalidArgument)
+        }
+
+    // Mark the proposal executed and persist it before any CPI. A replayed or racing
+    // ExecuteProposal then finds `executed` already set, and if the transfer below
+    // fails the whole transaction, including this write, is rolled back.
+    let proposal = &mut program_state.proposals[proposal_id as usize];
+    proposal.executed = true;
+    let agent_id = proposal.agent_id;
+    // Proposals created before ProposalAction describe a fixed transfer
+    let action = proposal.action.clone().or(match (proposal.target_account, proposal.transfer_lamports) {
+        (Some(to), Some(lamports)) => Some(ProposalAction::Transfer { to, lamports }),
+        _ => None,
+    });
+    let mut executed_actions = Vec::new();
+    // State changing actions are applied before the state is persisted
+    if let Some(ProposalAction::SetVotingPower { voter, power }) = action {
+        msg!("Executing proposal: Setting voting power.");
+        set_voting_power(program_state, voter, power)?;
+        executed_actions.push(ProposalAction::SetVotingPower { voter, power });
+    }
+    program_state.serialize(&mut &mut state_account.data.borrow_mut()[..])?;
+
+    // Execute Proposal Logic
+      if let Some(action) = action.filter(|action| !matches!(action, ProposalAction::SetVotingPower { .. })) {
+          msg!("Executing proposal action.");
+          let executed = execute_treasury_action(action, &program_state.agent_configs[agent_id as usize], accounts, program_id)?;
+          executed_actions.extend(executed);
+       }
+
+    // Persisted by the final serialize in process_instruction
+    program_state.proposals[proposal_id as usize].execution_result = Some(ExecutionResult {
+        actions: executed_actions,
+        timestamp: current_time,
+        executor: accounts.iter().find(|account| account.is_signer).map(|account| *account.key),
+    });
+      msg!("Proposal Executed with ID: {}", proposal_id);
+      Ok(())
+}
+
+// Runs a proposal action with the treasury PDA as signer and returns it as run.
+// Transfers always leave the treasury rent exempt. With a fee configured the fee is
+// taken out of the transfer amount and sent to the fee account, the recipient gets
+// the remainder, so the treasury is debited the proposal's amount either way.
+// Accounts: [state, treasury PDA, recipient, system program, fee account (with a fee)] for transfers,
+//           [state, treasury PDA, target program, accounts in `accounts_meta`...] for GenericCpi
+fn execute_treasury_action(
+    action: ProposalAction,
+    config: &AgentConfig,
+    accounts: &[AccountInfo],
+    program_id: &Pubkey,
+) -> Result<Vec<ProposalAction>, ProgramError> {
+    let accounts_iter = &mut accounts.iter().skip(1);
+    let treasury_account = next_account_info(accounts_iter)?;
+
+    let (treasury, bump) = Pubkey::find_program_address(&[TREASURY_SEED], program_id);
+    if *treasury_account.key != treasury {
+        msg!("Treasury account does not match the program treasury");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if let ProposalAction::GenericCpi { program_id: target_program, accounts_meta, data } = action.clone() {
+        if target_program == *program_id || !config.cpi_program_whitelist.contains(&target_program) {
+            msg!("Program {} is not whitelisted for proposal CPIs", target_program);
+            return Err(DaoError::ProgramNotWhitelisted.into());
+        }
+        invoke_treasury_cpi(target_program, accounts_meta, data, &treasury, accounts, &[TREASURY_SEED, &[bump]])?;
+        return Ok(vec![action]);
+    }
+
+    let recipient_account = next_account_info(accounts_iter)?;
+    let system_program_account = next_account_info(accounts_iter)?;
+    if *system_program_account.key != system_program::id() {
+        msg!("System program account expected");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let treasury_balance = treasury_account.lamports();
+    let rent_exempt_minimum = Rent::get()?.minimum_balance(treasury_account.data_len());
+    let available = treasury_balance.saturating_sub(rent_exempt_minimum);
+
+    let (to, lamports) = match action {
+        ProposalAction::Transfer { to, lamports } => {
+            if lamports > available {
+                msg!("Treasury can pay out at most {} lamports, {} requested", available, lamports);
+                return Err(ProgramError::InsufficientFunds);
+            }
+            (to, lamports)
+        }
+        ProposalAction::TransferFraction { to, basis_points } => {
+            let basis_points = basis_points.min(10_000) as u128;
+            let lamports = (treasury_balance as u128 * basis_points / 10_000) as u64;
+            (to, lamports.min(available))
+        }
+        ProposalAction::GenericCpi { .. } => unreachable!("GenericCpi is handled above"),
+        ProposalAction::SetVotingPower { .. } => unreachable!("SetVotingPower is applied by execute_proposal"),
+    };
+
+    if *recipient_account.key != to {
+        msg!("Recipient account does not match the proposal");
+        return Err(ProgramError::InvalidArgument);
+    }
+    // Proposals created before the target was validated are checked again here
+    check_transfer_target(&to, program_id)?;
+
+    let fee = (lamports as u128 * config.fee_basis_points.min(MAX_FEE_BASIS_POINTS) as u128 / 10_000) as u64;
+    let mut transfers = Vec::with_capacity(2);
+    if fee > 0 {
+        let fee_account = next_account_info(accounts_iter)?;
+        if *fee_account.key != config.fee_account {
+            msg!("Fee account does not match the agent config");
+            return Err(ProgramError::InvalidArgument);
+        }
+        msg!("Transferring a fee of {} lamports from the treasury to {}", fee, config.fee_account);
+        invoke_signed(
+            &system_instruction::transfer(&treasury, &config.fee_account, fee),
+            &[treasury_account.clone(), fee_account.clone(), system_program_account.clone()],
+            &[&[TREASURY_SEED, &[bump]]],
+        )?;
+        transfers.push(ProposalAction::Transfer { to: config.fee_account, lamports: fee });
+    }
+
+    let lamports = lamports - fee;
+    msg!("Transferring {} lamports from the treasury to {}", lamports, to);
+    invoke_signed(
+        &system_instruction::transfer(&treasury, &to, lamports),
+        &[treasury_account.clone(), recipient_account.clone(), system_program_account.clone()],
+        &[&[TREASURY_SEED, &[bump]]],
+    )?;
+    transfers.push(ProposalAction::Transfer { to, lamports });
+    Ok(transfers)
+}
+
+// Logs the treasury balance and the part of it proposals can move, i.e. the
+// balance above the rent-exempt reserve. TransferFraction proposals resolve
+// against the full balance, capped at that spendable amount.
+// Accounts: [state, treasury PDA]
+fn get_treasury_balance(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    let accounts_iter = &mut accounts.iter().skip(1);
+    let treasury_account = next_account_info(accounts_iter)?;
+
+    let (treasury, _) = Pubkey::find_program_address(&[TREASURY_SEED], program_id);
+    if *treasury_account.key != treasury {
+        msg!("Treasury account does not match the program treasury");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if treasury_account.lamports() == 0 {
+        msg!("Treasury account has not been funded yet");
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    let balance = treasury_account.lamports();
+    let spendable = balance.saturating_sub(Rent::get()?.minimum_balance(treasury_account.data_len()));
+    msg!("Treasury holds {} lamports, {} spendable", balance, spendable);
+    sol_log_data(&[&balance.to_le_bytes(), &spendable.to_le_bytes()]);
+    Ok(())
+}
+
+// Forwards a GenericCpi action. Every referenced account has to be present in
+// `accounts` with at least the requested privileges, so the proposal can't make
+// the call touch anything the executing transaction didn't explicitly pass in.
+fn invoke_treasury_cpi(
+    target_program: Pubkey,
+    accounts_meta: Vec<CpiAccountMeta>,
+    data: Vec<u8>,
+    treasury: &Pubkey,
+    accounts: &[AccountInfo],
+    treasury_seeds: &[&[u8]],
+) -> ProgramResult {
+    let find_account = |key: &Pubkey| match accounts.iter().find(|account| account.key == key) {
+        Some(account) => Ok(account),
+        None => {
+            msg!("Account {} referenced by the proposal CPI was not passed", key);
+            Err(ProgramError::from(DaoError::MissingCpiAccount))
+        }
+    };
+
+    let mut account_infos = vec![find_account(&target_program)?.clone()];
+    let mut metas = Vec::with_capacity(accounts_meta.len());
+    for meta in accounts_meta.iter() {
+        let account = find_account(&meta.pubkey)?;
+        if meta.is_writable && !account.is_writable {
+            msg!("Account {} must be writable for the proposal CPI", meta.pubkey);
+            return Err(DaoError::CpiAccountMismatch.into());
+        }
+        if meta.is_signer && meta.pubkey != *treasury && !account.is_signer {
+            msg!("Account {} must sign for the proposal CPI", meta.pubkey);
+            return Err(DaoError::CpiAccountMismatch.into());
+        }
+
+        metas.push(if meta.is_writable {
+            AccountMeta::new(meta.pubkey, meta.is_signer)
+        } else {
+            AccountMeta::new_readonly(meta.pubkey, meta.is_signer)
+        });
+        account_infos.push(account.clone());
+    }
+
+    msg!("Invoking {} with {} accounts from the treasury", target_program, metas.len());
+    invoke_signed(
+        &Instruction { program_id: target_program, accounts: metas, data },
+        &account_infos,
+        &[treasury_seeds],
+    )
+}
+
+// Accounts: [state, delegator (signer)]
+fn delegate_voting_power(
+    program_state: &mut ProgramState,
+    delegate_to: Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+
+    let accounts_iter = &mut accounts.iter().skip(1);
+    let delegator_account = next_account_info(accounts_iter)?;
+    if !delegator_account.is_signer {
+        msg!("Delegator signature missing");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let voter = delegator_account.key;
+    // Fetch the voter details and then update the voting power.
+    let voting_power = program_state.voting_power.get_mut(voter);
+    if let Some(voting_details) = voting_power{
+         voting_details.delegated_to = Some(delegate_to);
+    }else{
+        // Delegating never creates power, a voter without a record hands over nothing
+        let new_voting_details = VotingPower{
+            voter: *voter,
+            voting_power: 0,
+            delegated_to: Some(delegate_to),
+            last_vote_time: solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64,
+        };
+        program_state.voting_power.insert(*voter, new_voting_details);
+    }
+      msg!("Voting power delegated from {:?} to {:?}", voter, delegate_to);
+        Ok(())
+}
+
+// Only the owner(s) of an agent may assign voting power directly
+fn update_voting_power(
+    program_state: &mut ProgramState,
+    agent_id: u32,
+    voter: Pubkey,
+    voting_power: u64,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+      let config = match program_state.agent_configs.get(agent_id as usize) {
+          Some(config) => config,
+          None => {
+              msg!("Agent not found");
+              return Err(ProgramError::InvalidArgument);
+          }
+      };
+      verify_owner(config, accounts)?;
+      set_voting_power(program_state, voter, voting_power)
+}
+
+// Shared by UpdateVotingPower and the SetVotingPower proposal action
+fn set_voting_power(
+    program_state: &mut ProgramState,
+    voter: Pubkey,
+    voting_power: u64,
+) -> ProgramResult {
+      // The power map is shared by every agent, so the tightest configured cap applies
+      let cap = program_state.agent_configs.iter().filter_map(|config| config.max_total_voting_power).min();
+      if let Some(cap) = cap {
+          let current_total = saturating_sum(program_state.voting_power.values().map(|details| details.voting_power));
+          let previous_power = program_state.voting_power.get(&voter).map_or(0, |details| details.voting_power);
+          let new_total = current_total.saturating_sub(previous_power).saturating_add(voting_power);
+          if new_total > cap {
+              msg!("Total voting power {} would become {}, above the cap of {}", current_total, new_total, cap);
+              return Err(DaoError::VotingPowerCapExceeded.into());
+          }
+      }
+
+      let voting_details = program_state.voting_power.get_mut(&voter);
+      let current_time = solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64;
+
+        // Assigning power restarts the decay clock, this is how a fully decayed voter recovers
+        if let Some(voting_power_details) = voting_details {
+              voting_power_details.voting_power = voting_power;
+              voting_power_details.last_vote_time = current_time;
+        }else{
+             let new_voting_details = VotingPower{
+                voter: voter,
+                voting_power: voting_power,
+                delegated_to: None,
+                last_vote_time: current_time,
+            };
+             program_state.voting_power.insert(voter, new_voting_details);
+        }
+     msg!("Updated voting power of {:?} to {}", voter, voting_power);
+    Ok(())
+}
+
+// Marks every proposal whose voting ended without reaching quorum as expired and
+// logs the ids swept. Anyone may crank it, the outcome only depends on the clock.
+fn sweep_expired_proposals(program_state: &mut ProgramState) -> ProgramResult {
+    let clock = Clock::get()?;
+
+    let closed: Vec<u32> = program_state.proposals.iter()
+        .filter(|proposal| !proposal.executed && !proposal.expired && voting_closed(proposal, &clock))
+        .map(|proposal| proposal.id)
+        .collect();
+
+    // Outcomes computed here are cached for ExecuteProposal
+    let mut missed_quorum = Vec::new();
+    for proposal_id in closed {
+        if proposal_outcome(program_state, proposal_id, true).quorum_met {
+            continue;
+        }
+        let proposal = &program_state.proposals[proposal_id as usize];
+        let config = &program_state.agent_configs[proposal.agent_id as usize];
+        let grace_extension = if proposal.extended { 0 } else { config.grace_extension };
+        missed_quorum.push((proposal_id, grace_extension, config.grace_extension_slots));
+    }
+
+    // Proposals still owed their grace extension are extended rather than expired
+    let mut swept = Vec::new();
+    for (proposal_id, grace_extension, grace_extension_slots) in missed_quorum {
+        let proposal = &mut program_state.proposals[proposal_id as usize];
+        if grace_extension > 0 {
+            extend_voting(proposal, grace_extension, grace_extension_slots, &clock);
+        } else {
+            proposal.expired = true;
+            swept.push(proposal_id);
+        }
+    }
+
+    msg!("Expired {} proposals", swept.len());
+    sol_log_data(&[&swept.try_to_vec()?]);
+    Ok(())
+}
+
+// Voting closes once both the unix time and the slot are past the proposal's end.
+// Unix time can move non-monotonically across forks, so a timestamp that jumps
+// ahead alone can't close voting, nor open execution, early.
+fn voting_closed(proposal: &Proposal, clock: &Clock) -> bool {
+    clock.unix_timestamp as u64 > proposal.end_time && clock.slot > proposal.end_slot
+}
+
+// Reopens voting for `grace_extension` seconds past the later of end_time and now,
+// so voters get the full grace period even when execution comes late. end_slot
+// moves `grace_extension_slots` past the later of end_slot and the current slot.
+fn extend_voting(proposal: &mut Proposal, grace_extension: u64, grace_extension_slots: u64, clock: &Clock) {
+    proposal.end_time = proposal.end_time.max(clock.unix_timestamp as u64).saturating_add(grace_extension);
+    proposal.end_slot = proposal.end_slot.max(clock.slot).saturating_add(grace_extension_slots);
+    proposal.extended = true;
+    // Voting reopens, so the cached outcome no longer holds
+    proposal.cached_outcome = None;
+    msg!("Quorum not met, voting on proposal {} extended until {}", proposal.id, proposal.end_time);
+}
+
+// Logs whether a proposal passed and, once executed, what its execution did
+fn get_proposal_result(program_state: &ProgramState, proposal_id: u32) -> ProgramResult {
+    let proposal = match program_state.proposals.get(proposal_id as usize) {
+        Some(proposal) => proposal,
+        None => {
+            msg!("Proposal not found");
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+    let config = &program_state.agent_configs[proposal.agent_id as usize];
+    let (passed, quorum_met) = match &proposal.cached_outcome {
+        Some(outcome) => (outcome.passed, outcome.quorum_met),
+        None => check_proposal_result(proposal, config, program_state),
+    };
+
+    let result = ProposalResult {
+        proposal_id,
+        executed: proposal.executed,
+        expired: proposal.expired,
+        quorum_met,
+        passed,
+        execution_result: proposal.execution_result.clone(),
+    };
+    sol_log_data(&[&result.try_to_vec()?]);
+    msg!("Proposal {}: passed {}, executed {}", proposal_id, passed, proposal.executed);
+    Ok(())
+}
+
+// Works during and after voting. Power is the delegation-adjusted weight captured
+// when each vote was cast, the same figure execution tallies.
+fn get_vote_breakdown(program_state: &ProgramState, proposal_id: u32) -> ProgramResult {
+    let proposal = match program_state.proposals.get(proposal_id as usize) {
+        Some(proposal) => proposal,
+        None => {
+            msg!("Proposal not found");
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+
+    let mut options = vec![(0u32, 0u64); proposal.voting_options.len()];
+    for (voter, vote_index) in proposal.votes.iter() {
+        if let Some((count, power)) = options.get_mut(*vote_index as usize) {
+            *count += 1;
+            *power = power.saturating_add(proposal.vote_weights.get(voter).copied().unwrap_or(0));
+        }
+    }
+
+    let breakdown = VoteBreakdown {
+        proposal_id,
+        total_votes: options.iter().map(|(count, _)| count).sum(),
+        total_power: saturating_sum(options.iter().map(|(_, power)| *power)),
+        options,
+    };
+    msg!("Proposal {}: {} votes, {} power", proposal_id, breakdown.total_votes, breakdown.total_power);
+    sol_log_data(&[&breakdown.try_to_vec()?]);
+    Ok(())
+}
+
+// Works while voting is still open. Participating power uses the delegation-adjusted
+// weights captured when each vote was cast.
+fn get_quorum_progress(
+    program_state: &ProgramState,
+    proposal_id: u32,
+) -> ProgramResult {
+    if program_state.proposals.len() <= proposal_id as usize {
+        msg!("Proposal not found");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let proposal = &program_state.proposals[proposal_id as usize];
+    let config = &program_state.agent_configs[proposal.agent_id as usize];
+
+    let (participating, quorum_target) = match config.quorum_mode {
+        QuorumMode::PowerFraction => {
+            let total_voting_power = quorum_voting_power(program_state, config);
+            let participating_power = saturating_sum(proposal.vote_weights.values().copied());
+            (participating_power, (total_voting_power as f64 * QUORUM_POWER_FRACTION).ceil() as u64)
+        }
+        QuorumMode::VoterCount => (proposal.votes.len() as u64, config.min_voter_count as u64),
+    };
+
+    let remaining_fraction = if quorum_target == 0 || participating >= quorum_target {
+        0.0
+    } else {
+        (quorum_target - participating) as f64 / quorum_target as f64
+    };
+
+    let progress = QuorumProgress { proposal_id, participating, quorum_target, remaining_fraction };
+    sol_log_data(&[&progress.try_to_vec()?]);
+    msg!("Quorum progress: {}/{}", participating, quorum_target);
+    Ok(())
+}
+
+// Resolves a voter's power, following a delegation if the delegate has a record.
+// Voters without a record get a default power of 1.
+fn effective_voting_power(program_state: &ProgramState, voter: &Pubkey) -> u64 {
+      let mut voter_voting_power = 1;
+      let voting_power = program_state.voting_power.get(voter);
+      if let Some(voter_details) = voting_power{
+            // Get the voting power of the delegated to user if it exists
+            let delegate_to = voter_details.delegated_to;
+            if let Some(delegate) = delegate_to{
+               let delegate_voting_power = program_state.voting_power.get(&delegate);
+               if let Some(delegate_details) = delegate_voting_power {
+                    voter_voting_power = delegate_details.voting_power;
+                }else{
+                    voter_voting_power = voter_details.voting_power;
+                }
+           }else{
+                 voter_voting_power = voter_details.voting_power;
+           }
+      }
+      voter_voting_power
+}
+
+// Applies the config's decay schedule, see VotingPowerDecay for the formula
+fn decay_voting_power(decay: &VotingPowerDecay, voting_power: u64, last_vote_time: u64, current_time: u64) -> u64 {
+    match decay {
+        VotingPowerDecay::None => voting_power,
+        VotingPowerDecay::Linear { .. } if last_vote_time == 0 => voting_power,
+        VotingPowerDecay::Linear { grace_period, decay_period } => {
+            let elapsed = current_time.saturating_sub(last_vote_time);
+            if elapsed <= *grace_period {
+                return voting_power;
+            }
+            let decayed_for = (elapsed - grace_period).min(*decay_period);
+            if *decay_period == 0 {
+                return 0;
+            }
+            (voting_power as u128 * (decay_period - decayed_for) as u128 / *decay_period as u128) as u64
+        }
+    }
+}
+
+// Reads a voter's power from their governance token account. The account must be
+// a token program account for the configured mint, owned by the voter.
+fn token_voting_power(config: &AgentConfig, voter: &Pubkey, token_account: &AccountInfo) -> Result<u64, ProgramError> {
+    if token_account.owner != &spl_token::id() {
+        msg!("Voting token account is not owned by the token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let token = TokenAccount::unpack(&token_account.data.borrow())?;
+    if Some(token.mint) != config.governance_mint {
+        msg!("Token account mint does not match the governance mint");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if token.owner != *voter {
+        msg!("Token account is not owned by the voter");
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    Ok(token.amount)
+}
+
+// Outcome of a proposal, taken from its cache when set. With `closed` a freshly
+// computed outcome is cached, votes can't change it anymore.
+fn proposal_outcome(program_state: &mut ProgramState, proposal_id: u32, closed: bool) -> ProposalOutcome {
+    let proposal = &program_state.proposals[proposal_id as usize];
+    if let Some(outcome) = &proposal.cached_outcome {
+        return outcome.clone();
+    }
+    let config = &program_state.agent_configs[proposal.agent_id as usize];
+    let (passed, quorum_met) = check_proposal_result(proposal, config, program_state);
+    let option_power = tally_votes(proposal);
+    let outcome = ProposalOutcome {
+        passed,
+        quorum_met,
+        winning_option: winning_option(proposal, &option_power, &config.tie_break),
+        option_power,
+    };
+    if closed {
+        program_state.proposals[proposal_id as usize].cached_outcome = Some(outcome.clone());
+    }
+    outcome
+}
+
+// Sums the captured vote weights per voting option
+fn tally_votes(proposal: &Proposal) -> Vec<u64> {
+    let mut option_power = vec![0u64; proposal.voting_options.len()];
+    for (voter, vote_index) in proposal.votes.iter() {
+        if let Some(power) = option_power.get_mut(*vote_index as usize) {
+            *power = power.saturating_add(proposal.vote_weights.get(voter).copied().unwrap_or(0));
+        }
+    }
+    option_power
+}
+
+// Voting power is unbounded without max_total_voting_power, so totals saturate
+// instead of overflowing and aborting the instruction
+fn saturating_sum(powers: impl Iterator<Item = u64>) -> u64 {
+    powers.fold(0, |total, power| total.saturating_add(power))
+}
+
+// Option with the most weighted power, ties are settled by the config's TieBreak
+fn winning_option(proposal: &Proposal, option_power: &[u64], tie_break: &TieBreak) -> Option<u8> {
+    let top_power = option_power.iter().copied().max().unwrap_or(0);
+    if top_power == 0 {
+        return None;
+    }
+    let tied: Vec<u8> = (0..option_power.len())
+        .filter(|index| option_power[*index] == top_power)
+        .map(|index| index as u8)
+        .collect();
+    if tied.len() == 1 {
+        return Some(tied[0]);
+    }
+
+    match tie_break {
+        TieBreak::LowestIndex => Some(tied[0]),
+        TieBreak::Fail => None,
+        TieBreak::EarliestProposed => proposal.first_vote_order.iter().copied().find(|option| tied.contains(option)),
+    }
+}
+
+// Total power the PowerFraction quorum is measured against, see
+// AgentConfig::circulating_quorum
+fn quorum_voting_power(program_state: &ProgramState, config: &AgentConfig) -> u64 {
+    if !config.circulating_quorum {
+        return saturating_sum(program_state.voting_power.values().map(|details| details.voting_power));
+    }
+
+    let participants: HashSet<&Pubkey> = program_state.proposals.iter().flat_map(|proposal| proposal.votes.keys()).collect();
+    saturating_sum(program_state.voting_power.values()
+        .filter(|details| details.delegated_to.as_ref().map_or(true, |delegate| participants.contains(delegate)))
+        .map(|details| details.voting_power))
+}
+
+// Whether the passing option holds more than `early_pass_threshold` of the total voting power
+fn early_pass_reached(proposal: &Proposal, config: &AgentConfig, program_state: &ProgramState) -> bool {
+    if !config.early_pass_enabled {
+        return false;
+    }
+    let total_voting_power = saturating_sum(program_state.voting_power.values().map(|details| details.voting_power));
+    if total_voting_power == 0 {
+        return false;
+    }
+    let pass_power = tally_votes(proposal).get(proposal.pass_index as usize).copied().unwrap_or(0);
+    pass_power as f64 / total_voting_power as f64 > config.early_pass_threshold
+}
+
+fn check_proposal_result(proposal: &Proposal, config: &AgentConfig, program_state: &ProgramState) -> (bool, bool) {
+     // Get the total voting power available
+     let total_voting_power = quorum_voting_power(program_state, config);
+
+      let abstain = abstain_index(proposal);
+      let is_abstain = |vote: &u8| Some(*vote) == abstain;
+
+    // Calculate Total number of votes
+      let quorum_voters: Vec<&Pubkey> = proposal.votes.iter()
+          .filter(|(_, vote)| config.abstain_mode != AbstainMode::Ignore || !is_abstain(vote))
+          .map(|(voter, _)| voter)
+          .collect();
+      let quorum_met = match config.quorum_mode {
+          QuorumMode::PowerFraction => {
+              // Without any voting power the fraction is undefined, treat the quorum as unreachable
+              if total_voting_power == 0 {
+                  msg!("Quorum can't be met, the DAO has no voting power");
+                  return (false, false);
+              }
+              let participating_power = saturating_sum(quorum_voters.iter().filter_map(|voter| proposal.vote_weights.get(*voter).copied()));
+              participating_power as f64 / total_voting_power as f64 >= QUORUM_POWER_FRACTION
+          }
+          QuorumMode::VoterCount => quorum_voters.len() as u64 >= config.min_voter_count as u64,
+      };
+
+      if !quorum_met{
+        return (false, false);
+      }
+     
+      // Pass ratio: power behind the passing option over the participating power, which
+      // only includes abstain power when abstentions count against
+      let mut option_power = tally_votes(proposal);
+      if let Some(abstain) = abstain.filter(|_| config.abstain_mode != AbstainMode::CountAgainst) {
+          if let Some(power) = option_power.get_mut(abstain as usize) {
+              *power = 0;
+          }
+      }
+      let pass_power = option_power.get(proposal.pass_index as usize).copied().unwrap_or(0);
+      let participating_power = saturating_sum(option_power.iter().copied());
+
+      let mut vote_threshold_met = participating_power > 0
+          && pass_power as f64 / participating_power as f64 >= config.voting_threshold;
+
+      // When the passing option shares the top weighted power, it only passes if the tie-break picks it.
+      // Abstentions only compete for the top spot when they count against.
+      let tied_at_top = option_power.iter().enumerate()
+          .any(|(index, power)| index != proposal.pass_index as usize && *power == pass_power && pass_power > 0)
+          && option_power.iter().all(|power| *power <= pass_power);
+      if tied_at_top {
+          vote_threshold_met &= winning_option(proposal, &option_power, &config.tie_break) == Some(proposal.pass_index);
+      }
+      
       return (vote_threshold_met, quorum_met);
 
+}
+
+// Index of the "Abstain" voting option, unless it is the option counted towards passing
+fn abstain_index(proposal: &Proposal) -> Option<u8> {
+    proposal.voting_options.iter()
+        .position(|option| option.eq_ignore_ascii_case("abstain"))
+        .map(|index| index as u8)
+        .filter(|index| *index != proposal.pass_index)
+}
+
+fn find_instance_index(program_state: &ProgramState, instance_id: u32) -> Result<usize, ProgramError> {
+    match program_state.agent_instances.iter().position(|instance| instance.id == instance_id) {
+        Some(index) => Ok(index),
+        None => {
+            msg!("Agent instance not found");
+            Err(ProgramError::InvalidArgument)
+        }
+    }
+}
+
+// Logs the ids of proposals carrying `tag`, skipping the first `offset` matches
+// and returning at most `limit` (capped at MAX_PROPOSALS_PER_PAGE), followed by
+// the total number of matches so clients know when to stop paging.
+fn list_proposals_by_tag(
+    program_state: &ProgramState,
+    tag: String,
+    offset: u32,
+    limit: u32,
+) -> ProgramResult {
+    let matching: Vec<u32> = program_state.proposals.iter()
+        .filter(|proposal| proposal.tags.contains(&tag))
+        .map(|proposal| proposal.id)
+        .collect();
+
+    let page: Vec<u32> = matching.iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_PROPOSALS_PER_PAGE) as usize)
+        .copied()
+        .collect();
+
+    msg!("Returning {} of {} proposals tagged '{}'", page.len(), matching.len(), tag);
+    sol_log_data(&[&page.try_to_vec()?, &(matching.len() as u32).try_to_vec()?]);
+    Ok(())
+}
+
+// Logs (voter, delegated_to, voting_power) for the records of the voting_power map,
+// ordered by voter so pages are stable, skipping `offset` and returning at most
+// `limit` (capped at MAX_DELEGATIONS_PER_PAGE), followed by the total number of records
+fn get_delegation_graph(program_state: &ProgramState, offset: u32, limit: u32) -> ProgramResult {
+    let mut edges: Vec<(Pubkey, Option<Pubkey>, u64)> = program_state.voting_power.values()
+        .map(|details| (details.voter, details.delegated_to, details.voting_power))
+        .collect();
+    edges.sort_by_key(|(voter, _, _)| *voter);
+
+    let page: Vec<(Pubkey, Option<Pubkey>, u64)> = edges.iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_DELEGATIONS_PER_PAGE) as usize)
+        .copied()
+        .collect();
+
+    msg!("Returning {} of {} voting power records", page.len(), edges.len());
+    sol_log_data(&[&page.try_to_vec()?, &(edges.len() as u32).try_to_vec()?]);
+    Ok(())
+}
+
+// Logs (id, title, end_time) of the proposals open for voting right now, those past
+// their start_time whose voting hasn't closed and that aren't executed or expired.
+// Skips the first `offset` matches and returns at most `limit` (capped at
+// MAX_PROPOSALS_PER_PAGE), followed by the total number of active proposals
+fn get_active_proposals(program_state: &ProgramState, offset: u32, limit: u32) -> ProgramResult {
+    let clock = Clock::get()?;
+    let current_time = clock.unix_timestamp as u64;
+    let active: Vec<&Proposal> = program_state.proposals.iter()
+        .filter(|proposal| !proposal.executed && !proposal.expired)
+        .filter(|proposal| current_time >= proposal.start_time && !voting_closed(proposal, &clock))
+        .collect();
+
+    let page: Vec<(u32, String, u64)> = active.iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_PROPOSALS_PER_PAGE) as usize)
+        .map(|proposal| (proposal.id, proposal.title.clone(), proposal.end_time))
+        .collect();
+
+    msg!("Returning {} of {} active proposals", page.len(), active.len());
+    sol_log_data(&[&page.try_to_vec()?, &(active.len() as u32).try_to_vec()?]);
+    Ok(())
+}
+
+// Logs (id, title, status) of the proposals created by `proposer`, skipping the first
+// `offset` and returning at most `limit` (capped at MAX_PROPOSALS_PER_PAGE), followed
+// by the total number of proposals by `proposer`. A proposer with none gets an empty page.
+fn get_proposals_by_proposer(program_state: &ProgramState, proposer: Pubkey, offset: u32, limit: u32) -> ProgramResult {
+    let clock = Clock::get()?;
+    let authored: Vec<&Proposal> = program_state.proposals.iter()
+        .filter(|proposal| proposal.proposer == proposer)
+        .collect();
+
+    let page: Vec<(u32, String, ProposalStatus)> = authored.iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_PROPOSALS_PER_PAGE) as usize)
+        .map(|proposal| (proposal.id, proposal.title.clone(), proposal_status(proposal, &clock)))
+        .collect();
+
+    msg!("Returning {} of {} proposals by {:?}", page.len(), authored.len(), proposer);
+    sol_log_data(&[&page.try_to_vec()?, &(authored.len() as u32).try_to_vec()?]);
+    Ok(())
+}
+
+fn proposal_status(proposal: &Proposal, clock: &Clock) -> ProposalStatus {
+    if proposal.executed {
+        ProposalStatus::Executed
+    } else if proposal.expired {
+        ProposalStatus::Expired
+    } else if (clock.unix_timestamp as u64) < proposal.start_time {
+        ProposalStatus::Pending
+    } else if voting_closed(proposal, clock) {
+        ProposalStatus::Closed
+    } else {
+        ProposalStatus::Active
+    }
+}
+
+// Check that the agent owner has signed the transaction, or for a multi-sig agent that
+// `owner_threshold` of its owners have
+fn verify_owner(config: &AgentConfig, accounts: &[AccountInfo]) -> ProgramResult {
+    if !config.owners.is_empty() {
+        let signed = config.owners.iter()
+            .filter(|owner| accounts.iter().any(|account| account.is_signer && account.key == *owner))
+            .count();
+        if signed < config.owner_threshold as usize {
+            msg!("{} of {} required owner signatures present", signed, config.owner_threshold);
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        return Ok(());
+    }
+    let owner_signed = accounts.iter().any(|account| account.is_signer && *account.key == config.owner);
+    if !owner_signed {
+        msg!("Agent owner signature missing");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(())
+}
+
+// Reads the state account, upgrading accounts written with an older layout
+// instead of silently replacing them with a default state.
+fn load_program_state(data: &[u8]) -> Result<ProgramState, ProgramError> {
+    // A freshly allocated account is all zeroes
+    if data.iter().all(|byte| *byte == 0) {
+        return Ok(ProgramState { version: STATE_VERSION, ..ProgramState::default() });
+    }
+
+    // `deserialize` tolerates the zero padding after the serialized state
+    if data[0] == STATE_VERSION {
+        if let Ok(program_state) = ProgramState::deserialize(&mut &data[..]) {
+            return Ok(program_state);
+        }
+    }
+
+    // Accounts written before the version byte existed
+    if let Ok(legacy_state) = LegacyProgramState::deserialize(&mut &data[..]) {
+        // Migrated proposals are assigned to the first agent, which has to exist
+        if !legacy_state.proposals.is_empty() && legacy_state.agent_configs.is_empty() {
+            msg!("Legacy program state has proposals but no agent to assign them to");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        msg!("Migrating program state to version {}", STATE_VERSION);
+        return Ok(legacy_state.into());
+    }
+
+    msg!("Unknown program state version: {}", data[0]);
+    Err(ProgramError::InvalidAccountData)
+}
+
+// Unversioned layouts, kept only to migrate existing accounts
+#[derive(BorshDeserialize)]
+struct LegacyProposal {
+    id: u32,
+    proposer: Pubkey,
+    title: String,
+    description: String,
+    start_time: u64,
+    end_time: u64,
+    voting_options: Vec<String>,
+    votes: HashMap<Pubkey, u8>,
+    executed: bool,
+    target_account: Option<Pubkey>,
+    transfer_lamports: Option<u64>,
+}
+
+#[derive(BorshDeserialize)]
+struct LegacyAgentConfig {
+    owner: Pubkey,
+    description: String,
+    voting_threshold: f64,
+    quorum_threshold: f64,
+}
+
+#[derive(BorshDeserialize)]
+struct LegacyAgentInstance {
+    agent_id: u32,
+    status: u8,
+    start_time: u64,
+}
+
+#[derive(BorshDeserialize)]
+struct LegacyVotingPower {
+    voter: Pubkey,
+    voting_power: u64,
+    delegated_to: Option<Pubkey>,
+}
+
+#[derive(BorshDeserialize)]
+struct LegacyProgramState {
+    next_agent_id: u32,
+    next_proposal_id: u32,
+    agent_configs: Vec<LegacyAgentConfig>,
+    agent_instances: Vec<LegacyAgentInstance>,
+    proposals: Vec<LegacyProposal>,
+    voting_power: HashMap<Pubkey, LegacyVotingPower>,
+    last_analysis_time: u64,
+}
+
+impl From<LegacyProgramState> for ProgramState {
+    fn from(legacy: LegacyProgramState) -> Self {
+        let agent_configs: Vec<AgentConfig> = legacy.agent_configs.into_iter().map(|config| AgentConfig {
+            owner: config.owner,
+            description: config.description,
+            voting_threshold: config.voting_threshold,
+            quorum_threshold: config.quorum_threshold,
+            quorum_mode: QuorumMode::default(),
+            min_voter_count: 0,
+            voting_power_source: VotingPowerSource::default(),
+            governance_mint: None,
+            max_option_label_len: 0,
+            max_tags_per_proposal: 0,
+            voting_power_decay: VotingPowerDecay::default(),
+            cpi_program_whitelist: Vec::new(),
+            max_total_voting_power: None,
+            tie_break: TieBreak::default(),
+            min_voting_duration: 0,
+            max_voting_duration: 0,
+            max_open_proposals: 0,
+            early_pass_enabled: false,
+            early_pass_threshold: 0.0,
+            abstain_mode: AbstainMode::default(),
+            circulating_quorum: false,
+            owners: Vec::new(),
+            owner_threshold: 0,
+            fee_basis_points: 0,
+            fee_account: Pubkey::default(),
+            proposal_mint: None,
+            min_proposal_balance: 0,
+            grace_extension: 0,
+            execution_permission: ExecutionPermission::default(),
+            max_concurrent_instances: 0,
+            require_voting_power: false,
+            max_title_len: 0,
+            max_description_len: 0,
+            grace_extension_slots: 0,
+        }).collect();
+
+        // Legacy instance ids were their position in the vector
+        let agent_instances: Vec<AgentInstance> = legacy.agent_instances.into_iter().enumerate().map(|(index, instance)| AgentInstance {
+            id: index as u32,
+            agent_id: instance.agent_id,
+            status: instance.status,
+            start_time: instance.start_time,
+            last_heartbeat: instance.start_time,
+        }).collect();
+
+        // Legacy proposals were not tied to an agent, assign them to the first one
+        let proposals = legacy.proposals.into_iter().map(|proposal| Proposal {
+            id: proposal.id,
+            agent_id: 0,
+            proposer: proposal.proposer,
+            title: proposal.title,
+            description: proposal.description,
+            start_time: proposal.start_time,
+            end_time: proposal.end_time,
+            voting_options: proposal.voting_options,
+            votes: proposal.votes,
+            executed: proposal.executed,
+            target_account: proposal.target_account,
+            transfer_lamports: proposal.transfer_lamports,
+            ..Proposal::default()
+        }).collect();
+
+        // Migrated records have no vote history, last_vote_time 0 keeps them from decaying
+        let voting_power = legacy.voting_power.into_iter().map(|(voter, details)| (voter, VotingPower {
+            voter: details.voter,
+            voting_power: details.voting_power,
+            delegated_to: details.delegated_to,
+            last_vote_time: 0,
+        })).collect();
+
+        // Legacy accounts were in use already, the first agent's owner becomes the state owner
+        let owner = agent_configs.first().map(|config| config.owner).unwrap_or_default();
+
+        let mut program_state = ProgramState {
+            version: STATE_VERSION,
+            is_initialized: true,
+            owner,
+            next_agent_id: legacy.next_agent_id,
+            next_instance_id: agent_instances.len() as u32,
+            next_proposal_id: legacy.next_proposal_id,
+            agent_configs,
+            agent_instances,
+            proposals,
+            voting_power,
+            last_analysis_time: legacy.last_analysis_time,
+            pending_owners: HashMap::new(),
+            vote_nonces: HashMap::new(),
+        };
+
+        // Legacy votes carried no weight, resolve them against the stored power map
+        for index in 0..program_state.proposals.len() {
+            let vote_weights = program_state.proposals[index].votes.keys()
+                .map(|voter| (*voter, effective_voting_power(&program_state, voter)))
+                .collect();
+            program_state.proposals[index].vote_weights = vote_weights;
+        }
+        program_state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voting_power(voter: Pubkey, power: u64) -> (Pubkey, VotingPower) {
+        (voter, VotingPower { voter, voting_power: power, delegated_to: None, last_vote_time: 0 })
+    }
+
+    // Yes 60, No 20, Abstain 40 out of 120 total power, with a 0.6 pass threshold
+    // and a three voter quorum
+    fn abstain_vote_set(abstain_mode: AbstainMode) -> (Proposal, AgentConfig, ProgramState) {
+        let (yes, no, abstain) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let mut proposal = Proposal {
+            voting_options: vec!["Yes".to_string(), "No".to_string(), "Abstain".to_string()],
+            ..Proposal::default()
+        };
+        for (voter, vote_index, power) in [(yes, 0, 60), (no, 1, 20), (abstain, 2, 40)] {
+            proposal.votes.insert(voter, vote_index);
+            proposal.vote_weights.insert(voter, power);
+        }
+        let config = AgentConfig {
+            voting_threshold: 0.6,
+            quorum_mode: QuorumMode::VoterCount,
+            min_voter_count: 3,
+            abstain_mode,
+            ..AgentConfig::default()
+        };
+        let program_state = ProgramState {
+            voting_power: [voting_power(yes, 60), voting_power(no, 20), voting_power(abstain, 40)].into_iter().collect(),
+            ..ProgramState::default()
+        };
+        (proposal, config, program_state)
+    }
+
+    #[test]
+    fn abstain_ignore_leaves_abstentions_out_of_quorum() {
+        let (proposal, config, program_state) = abstain_vote_set(AbstainMode::Ignore);
+        // Two counted voters miss the three voter quorum
+        assert_eq!(check_proposal_result(&proposal, &config, &program_state), (false, false));
+    }
+
+    #[test]
+    fn abstain_count_against_adds_abstain_power_to_the_denominator() {
+        let (proposal, config, program_state) = abstain_vote_set(AbstainMode::CountAgainst);
+        // 60 / (60 + 20 + 40) = 0.5 stays below 0.6
+        assert_eq!(check_proposal_result(&proposal, &config, &program_state), (false, true));
+    }
+
+    #[test]
+    fn abstain_count_for_quorum_only_leaves_abstain_power_out_of_the_ratio() {
+        let (proposal, config, program_state) = abstain_vote_set(AbstainMode::CountForQuorumOnly);
+        // 60 / (60 + 20) = 0.75 clears 0.6
+        assert_eq!(check_proposal_result(&proposal, &config, &program_state), (true, true));
+    }
+
+    #[test]
+    fn pass_ratio_uses_the_configured_threshold() {
+        let (proposal, mut config, program_state) = abstain_vote_set(AbstainMode::CountAgainst);
+        config.voting_threshold = 0.5;
+        assert_eq!(check_proposal_result(&proposal, &config, &program_state), (true, true));
+    }
+
+    fn closed_proposal_state() -> ProgramState {
+        let (proposal, config, mut program_state) = abstain_vote_set(AbstainMode::CountForQuorumOnly);
+        program_state.agent_configs.push(config);
+        program_state.proposals.push(proposal);
+        program_state
+    }
+
+    #[test]
+    fn execute_retry_reuses_the_cached_outcome() {
+        let mut program_state = closed_proposal_state();
+        // The attempt whose transfer failed already computed the outcome
+        let first = proposal_outcome(&mut program_state, 0, true);
+        assert!(first.passed && first.quorum_met);
+        assert_eq!(first.option_power, vec![60, 20, 40]);
+
+        // A recount would now fail the proposal, the retry still sees the cached outcome
+        program_state.agent_configs[0].voting_threshold = 0.9;
+        assert_eq!(proposal_outcome(&mut program_state, 0, true), first);
+    }
+
+    #[test]
+    fn open_proposal_outcome_is_not_cached() {
+        let mut program_state = closed_proposal_state();
+        proposal_outcome(&mut program_state, 0, false);
+        assert!(program_state.proposals[0].cached_outcome.is_none());
+    }
+
+    #[test]
+    fn grace_extension_clears_the_cached_outcome() {
+        let mut program_state = closed_proposal_state();
+        proposal_outcome(&mut program_state, 0, true);
+        extend_voting(&mut program_state.proposals[0], 60, 150, &Clock::default());
+        assert!(program_state.proposals[0].cached_outcome.is_none());
+
+        program_state.agent_configs[0].voting_threshold = 0.9;
+        assert!(!proposal_outcome(&mut program_state, 0, true).passed);
+    }
+
+    // A Yes/No proposal with one fresh voter per (vote_index, power) and an idle holder of
+    // `idle_power` who doesn't vote
+    fn vote_set(votes: &[(u8, u64)], idle_power: u64) -> (Proposal, ProgramState) {
+        let mut proposal = Proposal {
+            voting_options: vec!["Yes".to_string(), "No".to_string()],
+            ..Proposal::default()
+        };
+        let mut program_state = ProgramState::default();
+        for (vote_index, power) in votes {
+            let voter = Pubkey::new_unique();
+            proposal.votes.insert(voter, *vote_index);
+            proposal.vote_weights.insert(voter, *power);
+            program_state.voting_power.extend([voting_power(voter, *power)]);
+        }
+        if idle_power > 0 {
+            program_state.voting_power.extend([voting_power(Pubkey::new_unique(), idle_power)]);
+        }
+        (proposal, program_state)
+    }
+
+    fn quorum_config(quorum_mode: QuorumMode) -> AgentConfig {
+        AgentConfig { voting_threshold: 0.6, quorum_mode, min_voter_count: 3, ..AgentConfig::default() }
+    }
+
+    #[test]
+    fn single_whale_meets_power_quorum_but_not_voter_count() {
+        let (proposal, program_state) = vote_set(&[(0, 100)], 0);
+        assert_eq!(check_proposal_result(&proposal, &quorum_config(QuorumMode::PowerFraction), &program_state), (true, true));
+        assert_eq!(check_proposal_result(&proposal, &quorum_config(QuorumMode::VoterCount), &program_state), (false, false));
+    }
+
+    #[test]
+    fn many_small_voters_meet_voter_count_but_not_power_quorum() {
+        // 3 of 10,000 power participates, below the 1% power fraction
+        let (proposal, program_state) = vote_set(&[(0, 1), (0, 1), (0, 1)], 9_997);
+        assert_eq!(check_proposal_result(&proposal, &quorum_config(QuorumMode::PowerFraction), &program_state), (false, false));
+        assert_eq!(check_proposal_result(&proposal, &quorum_config(QuorumMode::VoterCount), &program_state), (true, true));
+    }
+
+    // Yes and No tied at 50 power each, a 0.5 threshold so only the tie-break decides
+    fn tie_result(tie_break: TieBreak, first_vote_order: Vec<u8>) -> (bool, bool) {
+        let (mut proposal, program_state) = vote_set(&[(0, 50), (1, 50)], 0);
+        proposal.first_vote_order = first_vote_order;
+        let config = AgentConfig { voting_threshold: 0.5, tie_break, ..AgentConfig::default() };
+        check_proposal_result(&proposal, &config, &program_state)
+    }
+
+    #[test]
+    fn exact_tie_fails_under_fail() {
+        assert_eq!(tie_result(TieBreak::Fail, vec![0, 1]), (false, true));
+    }
+
+    #[test]
+    fn exact_tie_goes_to_the_lowest_index() {
+        assert_eq!(tie_result(TieBreak::LowestIndex, vec![1, 0]), (true, true));
+    }
+
+    #[test]
+    fn exact_tie_goes_to_the_earliest_proposed_option() {
+        assert_eq!(tie_result(TieBreak::EarliestProposed, vec![0, 1]), (true, true));
+        assert_eq!(tie_result(TieBreak::EarliestProposed, vec![1, 0]), (false, true));
+    }
+
+    #[test]
+    fn circulating_quorum_leaves_out_power_parked_with_inactive_delegates() {
+        // 10 power votes while 2,000 sits delegated to an address that never votes
+        let (proposal, mut program_state) = vote_set(&[(0, 10)], 0);
+        let (holder, inactive_delegate) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (_, mut parked) = voting_power(holder, 2_000);
+        parked.delegated_to = Some(inactive_delegate);
+        program_state.voting_power.insert(holder, parked);
+        program_state.proposals.push(proposal.clone());
+
+        let config = AgentConfig { voting_threshold: 0.6, ..AgentConfig::default() };
+        assert_eq!(quorum_voting_power(&program_state, &config), 2_010);
+        assert_eq!(check_proposal_result(&proposal, &config, &program_state), (false, false));
+
+        let config = AgentConfig { circulating_quorum: true, ..config };
+        assert_eq!(quorum_voting_power(&program_state, &config), 10);
+        assert_eq!(check_proposal_result(&proposal, &config, &program_state), (true, true));
+    }
+
+    #[test]
+    fn empty_dao_never_meets_quorum() {
+        let config = quorum_config(QuorumMode::PowerFraction);
+        let (proposal, program_state) = vote_set(&[], 0);
+        assert_eq!(check_proposal_result(&proposal, &config, &program_state), (false, false));
+
+        // A vote without any assigned power doesn't make the quorum reachable either
+        let mut proposal = proposal;
+        proposal.votes.insert(Pubkey::new_unique(), 0);
+        assert_eq!(check_proposal_result(&proposal, &config, &program_state), (false, false));
+    }
+
+    #[test]
+    fn empty_dao_refuses_proposals_only_when_configured() {
+        let program_state = ProgramState::default();
+        assert_eq!(validate_voting_power_exists(&program_state, &AgentConfig::default()), Ok(()));
+
+        let config = AgentConfig { require_voting_power: true, ..AgentConfig::default() };
+        assert_eq!(validate_voting_power_exists(&program_state, &config), Err(DaoError::NoVotingPower.into()));
+
+        let (_, program_state) = vote_set(&[], 10);
+        assert_eq!(validate_voting_power_exists(&program_state, &config), Ok(()));
+    }
+
+    // Clock::get off-chain goes through the syscall stubs, every test sees slot and time 1,000
+    struct TestSyscalls;
+
+    impl solana_program::program_stubs::SyscallStubs for TestSyscalls {
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            let clock = Clock { slot: 1_000, unix_timestamp: 1_000, ..Clock::default() };
+            unsafe { *(var_addr as *mut Clock) = clock };
+            solana_program::entrypoint::SUCCESS
+        }
+    }
+
+    fn use_test_clock() {
+        static STUBS: std::sync::Once = std::sync::Once::new();
+        STUBS.call_once(|| {
+            solana_program::program_stubs::set_syscall_stubs(Box::new(TestSyscalls));
+        });
+    }
+
+    #[test]
+    fn double_execution_in_the_same_slot_is_rejected() {
+        use_test_clock();
+        let mut program_state = closed_proposal_state();
+        let (state_key, program_id) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (mut lamports, mut data) = (0, vec![0u8; 10_000]);
+        let state_account = AccountInfo::new(&state_key, false, true, &mut lamports, &mut data, &program_id, false, 0);
+        let accounts = [state_account.clone()];
+
+        execute_proposal(&mut program_state, 0, &state_account, &accounts, &program_id).unwrap();
+
+        // `executed` reached the account before any action could run
+        let persisted = ProgramState::deserialize(&mut &state_account.data.borrow()[..]).unwrap();
+        assert!(persisted.proposals[0].executed);
+
+        // A racing executor sees the flag, whether it holds this state or reloads the account
+        let second = execute_proposal(&mut program_state, 0, &state_account, &accounts, &program_id);
+        assert_eq!(second, Err(ProgramError::InvalidArgument));
+        let mut reloaded = persisted;
+        let racing = execute_proposal(&mut reloaded, 0, &state_account, &accounts, &program_id);
+        assert_eq!(racing, Err(ProgramError::InvalidArgument));
+    }
 }
\ No newline at end of file
@@ -3,12 +3,47 @@ use solana_program::{
     account_info::{AccountInfo, next_account_info},
     entrypoint,
     entrypoint::ProgramResult,
+    keccak,
+    log::sol_log_data,
     msg,
     program_error::ProgramError,
     pubkey::Pubkey,
     system_program,
 };
-use std::collections::{HashMap};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+// Layout version written as the first byte of ProgramState. Bump it whenever a
+// stored struct changes and teach `load_program_state` to migrate the old layout.
+// Version 1 is the layout of the first release to carry the version byte, every
+// change made before that release ships as part of it.
+const STATE_VERSION: u8 = 1;
+
+// Maximum number of candles returned by a single GetMarketData query
+const MAX_CANDLES_PER_QUERY: usize = 100;
+
+// Hard ceiling on an indicator period. Seeding applies every stored candle once per
+// indicator at a small constant cost (a few hundred compute units), and an SMA keeps
+// `period` closes in state, so the period bounds both state size and per-call work.
+const MAX_INDICATOR_PERIOD: u32 = 200;
+
+// Longest per-indicator value history a config may retain, see `indicator_history_len`
+const MAX_INDICATOR_HISTORY: u32 = 64;
+
+// ATR period used when a config leaves `atr_period` at 0
+const DEFAULT_ATR_PERIOD: u32 = 14;
+
+// RSI levels whose crossing raises "RSI Overbought" / "RSI Oversold"
+const RSI_OVERBOUGHT: f64 = 70.0;
+const RSI_OVERSOLD: f64 = 30.0;
+
+// Williams %R levels (on its -100..0 scale) whose crossing raises "WILLR Overbought" / "WILLR Oversold"
+const WILLR_OVERBOUGHT: f64 = -20.0;
+const WILLR_OVERSOLD: f64 = -80.0;
+
+// Decimals of the scaled-integer indicator and signal values when a config leaves
+// `output_decimals` at 0. The ceiling keeps prices in the billions representable in an i64.
+const DEFAULT_OUTPUT_DECIMALS: u8 = 6;
+const MAX_OUTPUT_DECIMALS: u8 = 9;
 
 
 // Market Data Structs
@@ -20,6 +55,50 @@ pub struct MarketData {
   pub low: f64,
   pub close: f64,
   pub volume: f64,
+  pub backfilled: bool, // Synthesized by BackfillGaps rather than reported by the feed
+}
+
+// How BackfillGaps synthesizes missing candles
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default, PartialEq)]
+pub enum BackfillMethod {
+    #[default]
+    ForwardFill, // flat candles at the last close before the gap
+    Linear,      // closes interpolated between the candles around the gap
+}
+
+// Price of each candle the SMA, RSI, ROC and STDDEV series are built from.
+// WILLR compares the close against the high/low range and the ATR behind the
+// trailing stop uses the true range, both keep their own definitions.
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default, PartialEq)]
+pub enum PriceSource {
+    #[default]
+    Close,
+    Typical, // (high + low + close) / 3
+    Median,  // (high + low) / 2
+}
+
+impl PriceSource {
+    pub fn price(&self, candle: &MarketData) -> f64 {
+        match self {
+            PriceSource::Close => candle.close,
+            PriceSource::Typical => (candle.high + candle.low + candle.close) / 3.0,
+            PriceSource::Median => (candle.high + candle.low) / 2.0,
+        }
+    }
+}
+
+// Program specific errors, surfaced as ProgramError::Custom(code)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MarketError {
+    CandleExists = 0,
+    UnrepresentableValue = 1, // NaN, infinite or too large for the configured decimals
+    InsufficientHistory = 2,  // fewer usable candles than the config's min_history
+}
+
+impl From<MarketError> for ProgramError {
+    fn from(error: MarketError) -> Self {
+        ProgramError::Custom(error as u32)
+    }
 }
 
 
@@ -34,33 +113,226 @@ pub enum TimeFrame {
     OneDay,
 }
 
+impl TimeFrame {
+    // Expected spacing between consecutive candles, in seconds
+    pub fn seconds(&self) -> u64 {
+        match self {
+            TimeFrame::OneMinute => 60,
+            TimeFrame::FiveMinutes => 5 * 60,
+            TimeFrame::FifteenMinutes => 15 * 60,
+            TimeFrame::OneHour => 60 * 60,
+            TimeFrame::FourHours => 4 * 60 * 60,
+            TimeFrame::OneDay => 24 * 60 * 60,
+        }
+    }
+}
+
+// Missing interval between two stored candles, reported by DetectGaps
+#[derive(BorshSerialize, Debug)]
+pub struct CandleGap {
+    pub after_ts: u64,  // last candle before the gap
+    pub before_ts: u64, // first candle after the gap
+    pub missing: u64,   // number of candles expected in between
+}
+
+// Rolling indicator state, updated one candle at a time so new data never
+// requires a recompute over the whole history
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default)]
+pub struct IndicatorState {
+    pub period: u32,
+    pub window: VecDeque<f64>,   // SMA: prices currently inside the window, ROC and STDDEV: the last period + 1 prices
+    pub highs: VecDeque<f64>,    // WILLR: highs of the last period candles
+    pub lows: VecDeque<f64>,     // WILLR: lows of the last period candles
+    pub running_sum: f64,        // SMA: sum of `window`
+    pub avg_gain: f64,           // RSI: Wilder-smoothed average gain
+    pub avg_loss: f64,           // RSI: Wilder-smoothed average loss
+    pub min_volume: f64,         // Copied from the agent config when seeded
+    pub candles_seen: u32,
+    pub last_price: f64,         // Price of the newest candle applied, per `price_source`
+    pub last_timestamp: u64,     // Newest candle applied, reported as computed_at_candle_ts by GetIndicator
+    pub decimals: u8,            // Copied from the agent config when seeded
+    pub price_source: PriceSource, // Copied from the agent config when seeded
+    pub value: Option<i64>,      // Scaled by 10^decimals, None until enough candles have been seen
+    pub history_len: u32,        // Copied from the agent config when seeded, 0 keeps no history
+    pub history: VecDeque<(u64, i64)>, // (candle timestamp, value) of the last `history_len` values, oldest first
+}
+
+// Indicator value and freshness, reported by GetIndicator
+#[derive(BorshSerialize, Debug)]
+pub struct IndicatorSnapshot {
+    pub value: Option<i64>,         // scaled by 10^decimals
+    pub decimals: u8,
+    pub computed_at_candle_ts: u64, // newest candle the value includes
+    pub latest_candle_ts: u64,      // newest stored candle of the pair/timeframe
+    pub stale: bool,                // the two differ, reseed to catch up
+}
+
+// Size of the program state, reported by GetStateStats
+#[derive(BorshSerialize, Debug)]
+pub struct StateStats {
+    pub agent_count: u32,
+    pub instance_count: u32,
+    pub candle_count: u32,     // entries of the market_data map
+    pub indicator_count: u32,  // seeded indicators
+    pub signal_count: u32,
+    pub serialized_size: u64,  // bytes the state serializes to
+    pub allocated_size: u64,   // bytes allocated for the state account
+}
+
+// Result of ComputeDrawdown
+#[derive(BorshSerialize, Debug)]
+pub struct DrawdownReport {
+    pub max_drawdown_pct: i64,   // largest peak-to-trough decline of the close, in percent scaled by 10^decimals
+    pub decimals: u8,
+    pub peak_ts: Option<u64>,    // None when the closes never declined
+    pub trough_ts: Option<u64>,
+}
+
+// Result of ComputeSharpe
+#[derive(BorshSerialize, Debug)]
+pub struct SharpeReport {
+    pub sharpe: i64,     // (mean return - risk-free rate) / stddev of returns, scaled by 10^decimals
+    pub decimals: u8,
+    pub returns: u32,    // close-to-close returns the ratio was computed over
+    pub end_ts: u64,     // newest candle of the window
+}
+
+// Trailing stop for a long position, ratcheted by UpdateTrailingStop
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default)]
+pub struct TrailingStop {
+    pub level: f64,          // close - atr_multiplier * ATR, only ever raised
+    pub last_timestamp: u64, // candle the level was last evaluated on
+    pub triggered: bool,     // set once a close breaches the level, the stop is then frozen
+}
+
+// Signal raised for an agent's trading pair.
+// Severity runs from 0 (threshold barely crossed) to 100, per signal type:
+//   RSI Overbought:        (rsi - 70) / 30
+//   RSI Oversold:          (30 - rsi) / 30
+//   WILLR Overbought:      (%R + 20) / 20
+//   WILLR Oversold:        (-80 - %R) / 20
+//   Correlation Breakdown: (threshold - correlation) / (threshold + 1)
+//   StopHit:               (stop level - close) / (atr_multiplier * ATR)
+//   Momentum Negative:     -roc / 10, Momentum Positive: roc / 10 (a 10% move is maximal)
+//   Volume Spike:          (ratio - multiplier) / multiplier, where ratio = volume / average volume
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone)]
+pub struct Signal {
+    pub agent_id: u32,
+    pub trading_pair: String,
+    pub timeframe: TimeFrame,
+    pub signal_type: String,   // Example: "RSI Overbought"
+    pub timestamp: u64,
+    pub acknowledged: bool,    // Set by AckSignal once the owner's bot has handled it
+    pub severity: u8,
+    pub value: i64,            // Value behind the signal (RSI, ROC, correlation, the breaching close or the volume ratio) scaled by 10^decimals
+    pub decimals: u8,
+}
+
 // Agent Configuration
 #[derive(BorshDeserialize, BorshSerialize, Debug, Clone)]
 pub struct AgentConfig {
     pub owner: Pubkey,      // Owner of this agent
     pub description: String,  // Task description
-    pub trading_pair: String, // Example: "SOL/USDC"
+    // Each trading pair with its own indicator set
+    // Example: [("SOL/USDC", ["SMA_20", "RSI_14"]), ("ETH/USDC", ["SMA_50"])]
+    pub pair_indicators: Vec<(String, Vec<String>)>,
+    pub timeframes: Vec<TimeFrame>,
+    pub min_candle_volume: f64, // Candles below this volume are stored but skipped by indicators
+    pub max_indicator_period: u32, // Largest accepted period, 0 uses MAX_INDICATOR_PERIOD
+    pub correlation_threshold: f64, // Correlation below this raises a "Correlation Breakdown" signal
+    pub max_backfill_candles: u32, // Longest gap BackfillGaps will fill, 0 disables backfilling
+    pub exclude_backfilled: bool, // Seed indicators from feed candles only
+    pub atr_multiplier: f64, // k in the trailing stop level close - k * ATR
+    pub atr_period: u32, // Candles averaged into the ATR, 0 uses DEFAULT_ATR_PERIOD
+    pub align_timestamps: bool, // Floor incoming candle timestamps of tracked pairs to the timeframe boundary
+    pub output_decimals: u8, // Decimals of indicator and signal values, 0 uses DEFAULT_OUTPUT_DECIMALS
+    pub min_history: u32, // Usable candles a pair/timeframe needs before SeedIndicators computes anything
+    pub signal_cooldown: u64, // Seconds of candle time before the same signal type fires again for a pair, 0 disables throttling
+    // Multi-sig ownership: with `owners` set, privileged instructions need signatures from
+    // `owner_threshold` of them and `owner` is no longer consulted. Empty keeps `owner` as 1-of-1.
+    pub owners: Vec<Pubkey>,
+    pub owner_threshold: u8,
+    pub max_concurrent_instances: u32, // Instances with status created or running allowed per agent, 0 means no limit
+    pub price_source: PriceSource, // Takes effect for indicators seeded afterwards
+    pub max_candle_age: u64, // Seconds a candle of a tracked pair is kept before PruneOldCandles drops it, 0 keeps candles forever
+    pub indicator_history_len: u32, // Values each seeded indicator keeps for GetIndicatorHistory, at most MAX_INDICATOR_HISTORY, 0 keeps none
+}
+
+// Previous single-pair config layout, still accepted by CreateSinglePairAgent and
+// stored by accounts written before the version byte existed
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone)]
+pub struct SinglePairAgentConfig {
+    pub owner: Pubkey,
+    pub description: String,
+    pub trading_pair: String,
     pub timeframes: Vec<TimeFrame>,
-    pub indicators: Vec<String>, // Example: ["SMA_20", "RSI_14"]
+    pub indicators: Vec<String>,
+}
+
+impl From<SinglePairAgentConfig> for AgentConfig {
+    fn from(config: SinglePairAgentConfig) -> Self {
+        AgentConfig {
+            owner: config.owner,
+            description: config.description,
+            pair_indicators: vec![(config.trading_pair, config.indicators)],
+            timeframes: config.timeframes,
+            min_candle_volume: 0.0,
+            max_indicator_period: 0,
+            correlation_threshold: 0.0,
+            max_backfill_candles: 0,
+            exclude_backfilled: false,
+            atr_multiplier: 0.0,
+            atr_period: 0,
+            align_timestamps: false,
+            output_decimals: 0,
+            min_history: 0,
+            signal_cooldown: 0,
+            owners: Vec::new(),
+            owner_threshold: 0,
+            max_concurrent_instances: 0,
+            price_source: PriceSource::default(),
+            max_candle_age: 0,
+            indicator_history_len: 0,
+        }
+    }
 }
 
 // Agent Instance Structure
 #[derive(BorshDeserialize, BorshSerialize, Debug, Clone)]
 pub struct AgentInstance {
+    pub id: u32,
     pub agent_id: u32,        // ID of the agent config
     pub status: u8,         // 0: created, 1: running, 2: completed, 3: error
     pub start_time: u64,
+    pub last_heartbeat: u64, // Time of the worker's last Heartbeat, starts at start_time
 }
 
 
 // Program State (Account Data)
 #[derive(BorshDeserialize, BorshSerialize, Debug, Default)]
 pub struct ProgramState {
+    pub version: u8,
+    pub is_initialized: bool,
+    pub owner: Pubkey,             // Recorded once by Initialize, must sign CreateAgent
     pub next_agent_id: u32,        // Counter to assign unique ids for agents
+    pub next_instance_id: u32,     // Counter for instance ids, ids are never reused once an instance is closed
     pub agent_configs: Vec<AgentConfig>,
     pub agent_instances: Vec<AgentInstance>,
     // Mapping of (TradingPair, TimeFrame, Timestamp) -> Market Data
     pub market_data: HashMap<(String, TimeFrame, u64), MarketData>,
+    // Mapping of (AgentId, TradingPair, TimeFrame, Indicator) -> Rolling indicator state. Keyed by
+    // agent since the state carries that agent's volume filter, decimals, price source and history
+    pub computed_indicators: HashMap<(u32, String, TimeFrame, String), IndicatorState>,
+    pub signals: Vec<Signal>,
+    // Mapping of (TradingPair, TimeFrame) -> gaps found by the last DetectGaps run
+    pub gap_counts: HashMap<(String, TimeFrame), u32>,
+    // Mapping of (AgentId, TradingPair, TimeFrame) -> Trailing stop of the agent's long position
+    pub trailing_stops: HashMap<(u32, String, TimeFrame), TrailingStop>,
+    pub pending_owners: HashMap<u32, Pubkey>, // AgentId -> owner named by TransferAgentOwnership, awaiting acceptance
+    // Mapping of (AgentId, TradingPair, SignalType) -> timestamp of the last signal emitted
+    pub last_signal_times: HashMap<(u32, String, String), u64>,
+    // Mapping of (TradingPair, TimeFrame) -> timestamp of the newest stored candle
+    pub latest_candles: HashMap<(String, TimeFrame), u64>,
 }
 
 
@@ -70,7 +342,35 @@ pub enum AgentInstruction {
     CreateAgent(AgentConfig),
     CreateAgentInstance { agent_id: u32 },
     UpdateAgentInstanceStatus { agent_id: u32, instance_id: u32, status: u8 },
-    UpdateMarketData{trading_pair: String, timeframe: TimeFrame, market_data: MarketData},
+    UpdateMarketData{agent_id: u32, trading_pair: String, timeframe: TimeFrame, market_data: MarketData, overwrite: bool}, // signed by the owner(s) of `agent_id`
+    GetMarketData { trading_pair: String, timeframe: TimeFrame, start_ts: u64, end_ts: u64 },
+    SeedIndicators { agent_id: u32 },
+    CreateSinglePairAgent(SinglePairAgentConfig),
+    AckSignal { index: u32 },
+    ClearAckedSignals { agent_id: u32 },
+    Initialize { owner: Pubkey },
+    Correlation { agent_id: u32, pair_a: String, pair_b: String, timeframe: TimeFrame, window: u32 },
+    Heartbeat { instance_id: u32 },
+    GetStaleInstances { max_age: u64 },
+    DetectGaps { trading_pair: String, timeframe: TimeFrame },
+    BackfillGaps { agent_id: u32, trading_pair: String, timeframe: TimeFrame, method: BackfillMethod },
+    UpdateTrailingStop { agent_id: u32, trading_pair: String, timeframe: TimeFrame },
+    ResetTrailingStop { agent_id: u32, trading_pair: String, timeframe: TimeFrame },
+    GetAgentConfig { agent_id: u32 },
+    GetAgentInstance { instance_id: u32 },
+    ComputeDrawdown { trading_pair: String, timeframe: TimeFrame, window: u32 },
+    ExportChecksum,
+    GetSignals { min_severity: u8 },
+    TransferAgentOwnership { agent_id: u32, new_owner: Pubkey },
+    AcceptAgentOwnership { agent_id: u32 },
+    DetectVolumeSpike { agent_id: u32, trading_pair: String, timeframe: TimeFrame, window: u32, multiplier: f64 },
+    GetIndicator { agent_id: u32, trading_pair: String, timeframe: TimeFrame, indicator: String },
+    GetMarketDataLatest { trading_pair: String, timeframe: TimeFrame },
+    PruneOldCandles { trading_pair: String, timeframe: TimeFrame },
+    GetIndicatorHistory { agent_id: u32, trading_pair: String, timeframe: TimeFrame, indicator: String, limit: u32 },
+    GetStateStats,
+    ComputeSharpe { trading_pair: String, timeframe: TimeFrame, window: u32, risk_free_bps: i64 },
+    CloseAgentInstance { instance_id: u32 },
 }
 
 // Entrypoint
@@ -88,20 +388,44 @@ pub fn process_instruction(
      let accounts_iter = &mut accounts.iter();
     let state_account = next_account_info(accounts_iter)?;
 
-    if !state_account.is_writable {
+    // Read-only queries don't write the state back and may pass it read-only
+    let read_only = matches!(
+        instruction,
+        AgentInstruction::ExportChecksum
+            | AgentInstruction::GetStateStats
+            | AgentInstruction::GetSignals { .. }
+            | AgentInstruction::GetIndicator { .. }
+            | AgentInstruction::GetIndicatorHistory { .. }
+            | AgentInstruction::GetMarketDataLatest { .. }
+            | AgentInstruction::GetMarketData { .. }
+            | AgentInstruction::ComputeDrawdown { .. }
+            | AgentInstruction::ComputeSharpe { .. }
+            | AgentInstruction::GetAgentConfig { .. }
+            | AgentInstruction::GetAgentInstance { .. }
+            | AgentInstruction::GetStaleInstances { .. }
+    );
+    if !read_only && !state_account.is_writable {
         msg!("Program state account is not writeable");
         return Err(ProgramError::InvalidArgument);
     }
     
     // Load Program state (if available) or create a new one if not initialized
-    let mut program_state = ProgramState::try_from_slice(&state_account.data.borrow())
-         .unwrap_or_default();
+    let mut program_state = load_program_state(&state_account.data.borrow())?;
 
 
     match instruction {
+        AgentInstruction::Initialize { owner } => {
+            msg!("Initializing program state...");
+            initialize(&mut program_state, owner, program_id, state_account, accounts)?;
+        }
+        // Everything else needs an initialized state
+        _ if !program_state.is_initialized => {
+            msg!("Program state is not initialized");
+            return Err(ProgramError::UninitializedAccount);
+        }
          AgentInstruction::CreateAgent(config) => {
             msg!("Creating agent config...");
-            create_agent(&mut program_state, config, program_id, state_account)?;
+            create_agent(&mut program_state, config, program_id, state_account, accounts)?;
 
         }
         AgentInstruction::CreateAgentInstance { agent_id } => {
@@ -113,9 +437,129 @@ pub fn process_instruction(
             msg!("Updating agent instance status...");
              update_agent_instance_status(&mut program_state, agent_id, instance_id, status, state_account)?;
        }
-       AgentInstruction::UpdateMarketData{trading_pair, timeframe, market_data} => {
+        AgentInstruction::CloseAgentInstance { instance_id } => {
+            msg!("Closing agent instance...");
+            close_agent_instance(&mut program_state, instance_id, accounts)?;
+        }
+       AgentInstruction::UpdateMarketData{agent_id, trading_pair, timeframe, market_data, overwrite} => {
             msg!("Updating market data");
-            update_market_data(&mut program_state, trading_pair, timeframe, market_data, state_account)?;
+            update_market_data(&mut program_state, agent_id, trading_pair, timeframe, market_data, overwrite, accounts)?;
+        }
+        AgentInstruction::GetMarketData { trading_pair, timeframe, start_ts, end_ts } => {
+            msg!("Fetching market data");
+            get_market_data(&program_state, trading_pair, timeframe, start_ts, end_ts)?;
+            return Ok(());
+        }
+        AgentInstruction::SeedIndicators { agent_id } => {
+            msg!("Seeding indicators");
+            seed_indicators(&mut program_state, agent_id, accounts)?;
+        }
+        AgentInstruction::CreateSinglePairAgent(config) => {
+            msg!("Creating agent config from single-pair layout...");
+            create_agent(&mut program_state, config.into(), program_id, state_account, accounts)?;
+        }
+        AgentInstruction::AckSignal { index } => {
+            msg!("Acknowledging signal");
+            ack_signal(&mut program_state, index, accounts)?;
+        }
+        AgentInstruction::ClearAckedSignals { agent_id } => {
+            msg!("Clearing acknowledged signals");
+            clear_acked_signals(&mut program_state, agent_id, accounts)?;
+        }
+        AgentInstruction::Correlation { agent_id, pair_a, pair_b, timeframe, window } => {
+            msg!("Computing pair correlation");
+            compute_correlation(&mut program_state, agent_id, pair_a, pair_b, timeframe, window)?;
+        }
+        AgentInstruction::Heartbeat { instance_id } => {
+            msg!("Recording instance heartbeat...");
+            heartbeat(&mut program_state, instance_id, accounts)?;
+        }
+        AgentInstruction::GetStaleInstances { max_age } => {
+            msg!("Listing stale instances...");
+            get_stale_instances(&program_state, max_age)?;
+            return Ok(());
+        }
+        AgentInstruction::DetectGaps { trading_pair, timeframe } => {
+            msg!("Detecting candle gaps");
+            detect_gaps(&mut program_state, trading_pair, timeframe)?;
+        }
+        AgentInstruction::BackfillGaps { agent_id, trading_pair, timeframe, method } => {
+            msg!("Backfilling candle gaps");
+            backfill_gaps(&mut program_state, agent_id, trading_pair, timeframe, method, accounts)?;
+        }
+        AgentInstruction::UpdateTrailingStop { agent_id, trading_pair, timeframe } => {
+            msg!("Updating trailing stop");
+            update_trailing_stop(&mut program_state, agent_id, trading_pair, timeframe)?;
+        }
+        AgentInstruction::ResetTrailingStop { agent_id, trading_pair, timeframe } => {
+            msg!("Resetting trailing stop");
+            reset_trailing_stop(&mut program_state, agent_id, trading_pair, timeframe, accounts)?;
+        }
+        AgentInstruction::GetAgentConfig { agent_id } => {
+            msg!("Fetching agent config...");
+            get_agent_config(&program_state, agent_id)?;
+            return Ok(());
+        }
+        AgentInstruction::GetAgentInstance { instance_id } => {
+            msg!("Fetching agent instance...");
+            get_agent_instance(&program_state, instance_id)?;
+            return Ok(());
+        }
+        AgentInstruction::ComputeDrawdown { trading_pair, timeframe, window } => {
+            msg!("Computing drawdown");
+            compute_drawdown(&program_state, trading_pair, timeframe, window)?;
+            return Ok(());
+        }
+        AgentInstruction::ComputeSharpe { trading_pair, timeframe, window, risk_free_bps } => {
+            msg!("Computing Sharpe ratio");
+            compute_sharpe(&program_state, trading_pair, timeframe, window, risk_free_bps)?;
+            return Ok(());
+        }
+        AgentInstruction::ExportChecksum => {
+            msg!("Exporting state checksum...");
+            export_checksum(&program_state)?;
+            return Ok(());
+        }
+        AgentInstruction::GetStateStats => {
+            msg!("Fetching state stats...");
+            get_state_stats(&program_state, state_account)?;
+            return Ok(());
+        }
+        AgentInstruction::GetSignals { min_severity } => {
+            msg!("Fetching signals");
+            get_signals(&program_state, min_severity)?;
+            return Ok(());
+        }
+        AgentInstruction::TransferAgentOwnership { agent_id, new_owner } => {
+            msg!("Transferring agent ownership...");
+            transfer_agent_ownership(&mut program_state, agent_id, new_owner, accounts)?;
+        }
+        AgentInstruction::AcceptAgentOwnership { agent_id } => {
+            msg!("Accepting agent ownership...");
+            accept_agent_ownership(&mut program_state, agent_id, accounts)?;
+        }
+        AgentInstruction::DetectVolumeSpike { agent_id, trading_pair, timeframe, window, multiplier } => {
+            msg!("Detecting volume spike");
+            detect_volume_spike(&mut program_state, agent_id, trading_pair, timeframe, window, multiplier)?;
+        }
+        AgentInstruction::GetIndicator { agent_id, trading_pair, timeframe, indicator } => {
+            msg!("Fetching indicator");
+            get_indicator(&program_state, agent_id, trading_pair, timeframe, indicator)?;
+            return Ok(());
+        }
+        AgentInstruction::GetMarketDataLatest { trading_pair, timeframe } => {
+            msg!("Fetching latest candle");
+            get_market_data_latest(&program_state, trading_pair, timeframe)?;
+            return Ok(());
+        }
+        AgentInstruction::PruneOldCandles { trading_pair, timeframe } => {
+            msg!("Pruning old candles");
+            prune_old_candles(&mut program_state, trading_pair, timeframe)?;
+        }
+        AgentInstruction::GetIndicatorHistory { agent_id, trading_pair, timeframe, indicator, limit } => {
+            msg!("Fetching indicator history");
+            get_indicator_history(&program_state, agent_id, trading_pair, timeframe, indicator, limit)?;
+            return Ok(());
         }
     }
 
@@ -126,11 +570,39 @@ pub fn process_instruction(
 }
 
 // Instruction implementations
+// The owner must sign, it is the only key allowed to create agents afterwards
+fn initialize(
+    program_state: &mut ProgramState,
+    owner: Pubkey,
+    program_id: &Pubkey,
+    state_account: &AccountInfo,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    if state_account.owner != program_id {
+        msg!("Incorrect owner for program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if program_state.is_initialized {
+        msg!("Program state is already initialized");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+    if !accounts.iter().any(|account| account.is_signer && *account.key == owner) {
+        msg!("Owner signature missing");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    program_state.is_initialized = true;
+    program_state.owner = owner;
+    msg!("Initialized program state with owner: {:?}", owner);
+    Ok(())
+}
+
 fn create_agent(
     program_state: &mut ProgramState,
     config: AgentConfig,
     program_id: &Pubkey,
      state_account: &AccountInfo,
+    accounts: &[AccountInfo],
 ) -> ProgramResult {
 
     // Check if the signer is the owner of program
@@ -138,6 +610,25 @@ fn create_agent(
         msg!("Incorrect owner for program");
         return Err(ProgramError::IncorrectProgramId);
     }
+    if !accounts.iter().any(|account| account.is_signer && *account.key == program_state.owner) {
+        msg!("Program owner signature missing");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if config.output_decimals > MAX_OUTPUT_DECIMALS {
+        msg!("At most {} output decimals are supported", MAX_OUTPUT_DECIMALS);
+        return Err(ProgramError::InvalidArgument);
+    }
+    if config.indicator_history_len > MAX_INDICATOR_HISTORY {
+        msg!("At most {} indicator values can be retained", MAX_INDICATOR_HISTORY);
+        return Err(ProgramError::InvalidArgument);
+    }
+    if !config.owners.is_empty() {
+        let distinct = config.owners.iter().enumerate().all(|(index, owner)| !config.owners[..index].contains(owner));
+        if !distinct || config.owner_threshold == 0 || config.owner_threshold as usize > config.owners.len() {
+            msg!("Owner threshold must be between 1 and the number of distinct owners");
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
     
     let config_id = program_state.next_agent_id;
     program_state.agent_configs.push(config.clone());
@@ -160,13 +651,28 @@ fn create_agent_instance(
         return Err(ProgramError::InvalidArgument);
     }
 
+    let config = &program_state.agent_configs[agent_id as usize];
+    if config.max_concurrent_instances > 0 {
+        let active_instances = program_state.agent_instances.iter()
+            .filter(|instance| instance.agent_id == agent_id && instance.status <= 1)
+            .count();
+        if active_instances >= config.max_concurrent_instances as usize {
+            msg!("Agent {} already has {} of {} allowed instances created or running", agent_id, active_instances, config.max_concurrent_instances);
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
+
+    let start_time = solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64;
     let new_instance = AgentInstance {
+        id: program_state.next_instance_id,
         agent_id,
         status: 0, // Created status
-        start_time: solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64,
+        start_time,
+        last_heartbeat: start_time,
     };
 
      program_state.agent_instances.push(new_instance);
+     program_state.next_instance_id += 1;
 
      msg!("Created agent instance with agent ID: {}", agent_id);
 
@@ -180,12 +686,9 @@ fn update_agent_instance_status(
     status: u8,
     state_account: &AccountInfo,
 ) -> ProgramResult {
-    if program_state.agent_instances.len() <= instance_id as usize {
-        msg!("Agent instance not found");
-        return Err(ProgramError::InvalidArgument);
-    }
+    let instance_index = find_instance_index(program_state, instance_id)?;
 
-     let instance = program_state.agent_instances.get_mut(instance_id as usize).unwrap();
+     let instance = &mut program_state.agent_instances[instance_index];
      if instance.agent_id != agent_id {
         msg!("Incorrect agent ID for the requested instance");
         return Err(ProgramError::InvalidArgument)
@@ -196,16 +699,1516 @@ fn update_agent_instance_status(
      Ok(())
 }
 
+// Removes a completed or errored instance so the state account stops growing.
+// Instances live inside the state account today; if they move to their own
+// accounts this should also drain the instance account's lamports to the owner.
+fn close_agent_instance(
+    program_state: &mut ProgramState,
+    instance_id: u32,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let instance_index = find_instance_index(program_state, instance_id)?;
+    let instance = &program_state.agent_instances[instance_index];
+
+    let config = &program_state.agent_configs[instance.agent_id as usize];
+    verify_owner(config, accounts)?;
+
+    // Only completed (2) or errored (3) instances can be closed
+    if instance.status != 2 && instance.status != 3 {
+        msg!("Cannot close instance {} with status {}", instance_id, instance.status);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    program_state.agent_instances.remove(instance_index);
+    msg!("Closed agent instance with ID: {}", instance_id);
+    Ok(())
+}
+
+// First step of an ownership handoff: the current owner names the next owner,
+// who then has to accept. Naming another owner replaces a pending transfer.
+fn transfer_agent_ownership(
+    program_state: &mut ProgramState,
+    agent_id: u32,
+    new_owner: Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    // Check if agent exists
+    if program_state.agent_configs.len() <= agent_id as usize {
+        msg!("Agent not found");
+        return Err(ProgramError::InvalidArgument);
+    }
+    verify_owner(&program_state.agent_configs[agent_id as usize], accounts)?;
+
+    program_state.pending_owners.insert(agent_id, new_owner);
+    msg!("Agent {} ownership offered to {:?}", agent_id, new_owner);
+    Ok(())
+}
+
+// Second step: the pending owner signs to take over the agent
+fn accept_agent_ownership(
+    program_state: &mut ProgramState,
+    agent_id: u32,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let new_owner = match program_state.pending_owners.get(&agent_id) {
+        Some(new_owner) => *new_owner,
+        None => {
+            msg!("No ownership transfer pending for agent {}", agent_id);
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+    let new_owner_signed = accounts.iter().any(|account| account.is_signer && *account.key == new_owner);
+    if !new_owner_signed {
+        msg!("Pending owner signature missing");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // The new owner takes over alone, a multi-sig owner set is dissolved
+    let config = &mut program_state.agent_configs[agent_id as usize];
+    config.owner = new_owner;
+    config.owners.clear();
+    config.owner_threshold = 0;
+    program_state.pending_owners.remove(&agent_id);
+    msg!("Agent {} is now owned by {:?}", agent_id, new_owner);
+    Ok(())
+}
+
+// Records that the off-chain worker running an instance is still making progress.
+// The agent owner operates its instances, so the heartbeat must be signed by it.
+fn heartbeat(
+    program_state: &mut ProgramState,
+    instance_id: u32,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let instance_index = find_instance_index(program_state, instance_id)?;
+    let config = &program_state.agent_configs[program_state.agent_instances[instance_index].agent_id as usize];
+    verify_owner(config, accounts)?;
+
+    let current_time = solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64;
+    program_state.agent_instances[instance_index].last_heartbeat = current_time;
+    msg!("Heartbeat for instance {} at {}", instance_id, current_time);
+    Ok(())
+}
+
+// Logs the ids of created or running instances without a heartbeat in the last `max_age` seconds
+fn get_stale_instances(program_state: &ProgramState, max_age: u64) -> ProgramResult {
+    let current_time = solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64;
+    let stale: Vec<u32> = program_state.agent_instances.iter()
+        .filter(|instance| instance.status < 2 && current_time.saturating_sub(instance.last_heartbeat) > max_age)
+        .map(|instance| instance.id)
+        .collect();
+
+    msg!("Found {} stale instances", stale.len());
+    sol_log_data(&[&stale.try_to_vec()?]);
+    Ok(())
+}
+
+fn get_agent_config(program_state: &ProgramState, agent_id: u32) -> ProgramResult {
+    let config = match program_state.agent_configs.get(agent_id as usize) {
+        Some(config) => config,
+        None => {
+            msg!("Agent not found");
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+    sol_log_data(&[&config.try_to_vec()?]);
+    Ok(())
+}
+
+fn get_agent_instance(program_state: &ProgramState, instance_id: u32) -> ProgramResult {
+    let instance_index = find_instance_index(program_state, instance_id)?;
+    sol_log_data(&[&program_state.agent_instances[instance_index].try_to_vec()?]);
+    Ok(())
+}
+
+// Logs the keccak hash of the serialized state and its length so an off-chain
+// mirror can check that it serializes to exactly the same bytes
+fn export_checksum(program_state: &ProgramState) -> ProgramResult {
+    let serialized = program_state.try_to_vec()?;
+    let digest = keccak::hash(&serialized);
+    msg!("State checksum {} over {} bytes", digest, serialized.len());
+    sol_log_data(&[&digest.to_bytes(), &(serialized.len() as u64).to_le_bytes()]);
+    Ok(())
+}
+
+// Logs entry counts and the serialized state size against the account's allocated
+// size, so operators can alert before the state outgrows its account
+fn get_state_stats(program_state: &ProgramState, state_account: &AccountInfo) -> ProgramResult {
+    let stats = StateStats {
+        agent_count: program_state.agent_configs.len() as u32,
+        instance_count: program_state.agent_instances.len() as u32,
+        candle_count: program_state.market_data.len() as u32,
+        indicator_count: program_state.computed_indicators.len() as u32,
+        signal_count: program_state.signals.len() as u32,
+        serialized_size: program_state.try_to_vec()?.len() as u64,
+        allocated_size: state_account.data_len() as u64,
+    };
+    msg!("State uses {} of {} allocated bytes", stats.serialized_size, stats.allocated_size);
+    sol_log_data(&[&stats.try_to_vec()?]);
+    Ok(())
+}
+
 
+// Candles are keyed by (pair, timeframe, timestamp). Re-sending a stored timestamp
+// is rejected with CandleExists unless `overwrite` is set, in which case the last
+// write wins and the correction is logged. Seeded indicators have already consumed
+// the old candle, so a correction only reaches them after a reseed.
 fn update_market_data(
      program_state: &mut ProgramState,
+    agent_id: u32,
     trading_pair: String,
     timeframe: TimeFrame,
-    market_data: MarketData,
-     _state_account: &AccountInfo,
+    mut market_data: MarketData,
+    overwrite: bool,
+    accounts: &[AccountInfo],
 )->ProgramResult{
 
-     program_state.market_data.insert((trading_pair, timeframe, market_data.timestamp), market_data);
+    // Only the owner(s) of an agent may feed market data
+    let config = match program_state.agent_configs.get(agent_id as usize) {
+        Some(config) => config,
+        None => {
+            msg!("Agent not found");
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+    verify_owner(config, accounts)?;
+
+    // Only BackfillGaps may mark a candle as synthesized
+    market_data.backfilled = false;
+
+    // Agents tracking the pair can opt into aligned timestamps. Updates that fall inside
+    // the same bucket (e.g. 1m candles at :00 and :01) then share one key, the existence
+    // check below runs on the aligned timestamp so the last write only wins with `overwrite`.
+    let align = program_state.agent_configs.iter().any(|config| {
+        config.align_timestamps
+            && config.timeframes.contains(&timeframe)
+            && config.pair_indicators.iter().any(|(pair, _)| pair == &trading_pair)
+    });
+    if align {
+        market_data.timestamp = normalize_timestamp(market_data.timestamp, &timeframe);
+    }
+
+    let timestamp = market_data.timestamp;
+    let close = market_data.close;
+    let volume = market_data.volume;
+    let candle = market_data.clone();
+    let key = (trading_pair.clone(), timeframe.clone(), timestamp);
+    if let Some(existing) = program_state.market_data.get(&key) {
+        if !overwrite {
+            msg!("Candle {} already exists, set overwrite to correct it", timestamp);
+            return Err(MarketError::CandleExists.into());
+        }
+        msg!("Correcting candle {}: close {} -> {}", timestamp, existing.close, close);
+    }
+     program_state.market_data.insert(key, market_data);
+    let latest = program_state.latest_candles.entry((trading_pair.clone(), timeframe.clone())).or_insert(timestamp);
+    *latest = (*latest).max(timestamp);
+
+    // Apply the new candle to every agent's seeded indicators for this pair and timeframe
+    for ((indicator_agent_id, pair, tf, indicator), state) in program_state.computed_indicators.iter_mut() {
+        if pair != &trading_pair || tf != &timeframe {
+            continue;
+        }
+        if volume < state.min_volume {
+            msg!("Skipping illiquid candle {} for {}", timestamp, indicator);
+            continue;
+        }
+        if state.candles_seen > 0 && timestamp <= state.last_timestamp {
+            // Late or corrected candles can't be applied incrementally
+            msg!("Candle {} is not newer than {}, reseed required", timestamp, indicator);
+            continue;
+        }
+        let previous_value = state.value.map(|value| from_fixed(value, state.decimals));
+        apply_candle(indicator, state, &candle)?;
+
+        let value = match state.value {
+            Some(value) => from_fixed(value, state.decimals),
+            None => continue,
+        };
+        let (signal_type, severity) = match indicator_crossing(indicator, previous_value, value) {
+            Some(crossing) => crossing,
+            None => continue,
+        };
+        let config = match program_state.agent_configs.get(*indicator_agent_id as usize) {
+            Some(config) => config,
+            None => continue,
+        };
+        let tracks_indicator = config.timeframes.contains(&timeframe) && config.pair_indicators.iter()
+            .any(|(config_pair, indicators)| config_pair == &trading_pair && indicators.contains(indicator));
+        if tracks_indicator {
+            let decimals = output_decimals(config);
+            push_signal(&mut program_state.signals, &mut program_state.last_signal_times, config.signal_cooldown, Signal {
+                agent_id: *indicator_agent_id,
+                trading_pair: trading_pair.clone(),
+                timeframe: timeframe.clone(),
+                signal_type: signal_type.to_string(),
+                timestamp,
+                acknowledged: false,
+                severity,
+                value: to_fixed(value, decimals)?,
+                decimals,
+            });
+        }
+    }
     
     Ok(())
+}
+
+// Floors a timestamp to the start of its timeframe bucket
+fn normalize_timestamp(timestamp: u64, timeframe: &TimeFrame) -> u64 {
+    timestamp - timestamp % timeframe.seconds()
+}
+
+// Threshold crossings that raise a signal: RSI or Williams %R moving into overbought
+// or oversold territory and ROC momentum changing sign
+fn indicator_crossing(indicator: &str, previous_value: Option<f64>, value: f64) -> Option<(&'static str, u8)> {
+    match parse_indicator(indicator)? {
+        ("RSI", _) => {
+            if value > RSI_OVERBOUGHT && previous_value.map_or(true, |previous| previous <= RSI_OVERBOUGHT) {
+                Some(("RSI Overbought", signal_severity(value - RSI_OVERBOUGHT, 100.0 - RSI_OVERBOUGHT)))
+            } else if value < RSI_OVERSOLD && previous_value.map_or(true, |previous| previous >= RSI_OVERSOLD) {
+                Some(("RSI Oversold", signal_severity(RSI_OVERSOLD - value, RSI_OVERSOLD)))
+            } else {
+                None
+            }
+        }
+        ("WILLR", _) => {
+            if value > WILLR_OVERBOUGHT && previous_value.map_or(true, |previous| previous <= WILLR_OVERBOUGHT) {
+                Some(("WILLR Overbought", signal_severity(value - WILLR_OVERBOUGHT, -WILLR_OVERBOUGHT)))
+            } else if value < WILLR_OVERSOLD && previous_value.map_or(true, |previous| previous >= WILLR_OVERSOLD) {
+                Some(("WILLR Oversold", signal_severity(WILLR_OVERSOLD - value, 100.0 + WILLR_OVERSOLD)))
+            } else {
+                None
+            }
+        }
+        ("ROC", _) => {
+            let previous = previous_value?;
+            if value < 0.0 && previous >= 0.0 {
+                Some(("Momentum Negative", signal_severity(-value, 10.0)))
+            } else if value > 0.0 && previous <= 0.0 {
+                Some(("Momentum Positive", signal_severity(value, 10.0)))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+fn find_instance_index(program_state: &ProgramState, instance_id: u32) -> Result<usize, ProgramError> {
+    match program_state.agent_instances.iter().position(|instance| instance.id == instance_id) {
+        Some(index) => Ok(index),
+        None => {
+            msg!("Agent instance not found");
+            Err(ProgramError::InvalidArgument)
+        }
+    }
+}
+
+// Check that the agent owner has signed the transaction, or for a multi-sig agent that
+// `owner_threshold` of its owners have
+fn verify_owner(config: &AgentConfig, accounts: &[AccountInfo]) -> ProgramResult {
+    if !config.owners.is_empty() {
+        let signed = config.owners.iter()
+            .filter(|owner| accounts.iter().any(|account| account.is_signer && account.key == *owner))
+            .count();
+        if signed < config.owner_threshold as usize {
+            msg!("{} of {} required owner signatures present", signed, config.owner_threshold);
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        return Ok(());
+    }
+    let owner_signed = accounts.iter().any(|account| account.is_signer && *account.key == config.owner);
+    if !owner_signed {
+        msg!("Agent owner signature missing");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(())
+}
+
+// Logs the candles for a pair/timeframe within [start_ts, end_ts], oldest first.
+// At most MAX_CANDLES_PER_QUERY candles are returned, followed by the count, so
+// clients can page by narrowing the range past the last returned timestamp.
+fn get_market_data(
+    program_state: &ProgramState,
+    trading_pair: String,
+    timeframe: TimeFrame,
+    start_ts: u64,
+    end_ts: u64,
+) -> ProgramResult {
+    if start_ts > end_ts {
+        msg!("Invalid time range");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let mut candles = program_state.market_data.iter()
+        .filter(|((pair, tf, timestamp), _)| pair == &trading_pair && tf == &timeframe && *timestamp >= start_ts && *timestamp <= end_ts)
+        .map(|(_, data)| data)
+        .collect::<Vec<_>>();
+    candles.sort_by_key(|data| data.timestamp);
+    candles.truncate(MAX_CANDLES_PER_QUERY);
+
+    for candle in candles.iter() {
+        sol_log_data(&[&candle.try_to_vec()?]);
+    }
+    sol_log_data(&[&(candles.len() as u32).to_le_bytes()]);
+
+    msg!("Returned {} candles", candles.len());
+    Ok(())
+}
+
+// Logs the newest candle of a pair/timeframe, looked up through `latest_candles`
+// instead of scanning the stored history
+fn get_market_data_latest(program_state: &ProgramState, trading_pair: String, timeframe: TimeFrame) -> ProgramResult {
+    let candle = program_state.latest_candles.get(&(trading_pair.clone(), timeframe.clone()))
+        .and_then(|timestamp| program_state.market_data.get(&(trading_pair.clone(), timeframe.clone(), *timestamp)));
+    let candle = match candle {
+        Some(candle) => candle,
+        None => {
+            msg!("No candles stored for {}", trading_pair);
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+
+    sol_log_data(&[&candle.try_to_vec()?]);
+    msg!("Latest candle of {} at {}", trading_pair, candle.timestamp);
+    Ok(())
+}
+
+// Builds the rolling indicator state from the stored history. Afterwards each
+// UpdateMarketData call only applies the new candle.
+// Candles below the config's min_candle_volume are left out of every window, so
+// a pair can have enough raw candles but fewer than `n` qualifying ones.
+// A pair/timeframe with fewer usable candles than `min_history` is logged and skipped
+// so the other pairs still get seeded. The call only fails with InsufficientHistory
+// when every pair/timeframe was skipped, which covers an agent tracking a single one.
+// Past that check, indicators whose window doesn't fit the usable candles are
+// skipped rather than computed over a shorter window, and the others are still
+// seeded. Only the agent's owner(s) may reseed, as reseeding replaces the agent's
+// indicator state.
+fn seed_indicators(
+    program_state: &mut ProgramState,
+    agent_id: u32,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    // Check if agent exists
+    let config = match program_state.agent_configs.get(agent_id as usize) {
+        Some(config) => config.clone(),
+        None => {
+            msg!("Agent not found");
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+    verify_owner(&config, accounts)?;
+
+    let (mut seeded_targets, mut skipped_targets) = (0, 0);
+    for (trading_pair, indicators) in &config.pair_indicators {
+        for timeframe in &config.timeframes {
+            let mut candles = program_state.market_data.iter()
+                .filter(|((pair, tf, _), _)| pair == trading_pair && tf == timeframe)
+                .map(|(_, data)| data)
+                .collect::<Vec<_>>();
+            candles.sort_by_key(|data| data.timestamp);
+            let total_candles = candles.len();
+            candles.retain(|data| data.volume >= config.min_candle_volume && !(config.exclude_backfilled && data.backfilled));
+            if candles.len() < config.min_history as usize {
+                msg!("Skipping {} {:?}: {} usable candles ({} stored), {} required", trading_pair, timeframe, candles.len(), total_candles, config.min_history);
+                skipped_targets += 1;
+                continue;
+            }
+            seeded_targets += 1;
+
+            for indicator in indicators {
+                let (kind, period) = match parse_indicator(indicator) {
+                    Some(parsed) => parsed,
+                    None => {
+                        msg!("Unsupported indicator: {}", indicator);
+                        return Err(ProgramError::InvalidArgument);
+                    }
+                };
+                let max_period = max_indicator_period(&config);
+                if period > max_period as usize {
+                    msg!("Indicator {} exceeds the maximum period of {}", indicator, max_period);
+                    return Err(ProgramError::InvalidArgument);
+                }
+
+                // RSI, ROC and STDDEV need one extra candle since they work on price changes
+                let required = if kind == "SMA" || kind == "WILLR" { period } else { period + 1 };
+                if candles.len() < required {
+                    msg!("Skipping {}: {} of {} candles are usable", indicator, candles.len(), required);
+                    continue;
+                }
+
+                let mut state = IndicatorState {
+                    period: period as u32,
+                    min_volume: config.min_candle_volume,
+                    decimals: output_decimals(&config),
+                    price_source: config.price_source.clone(),
+                    history_len: config.indicator_history_len,
+                    ..IndicatorState::default()
+                };
+                for candle in candles.iter() {
+                    apply_candle(indicator, &mut state, candle)?;
+                }
+
+                msg!("Seeded {} from {} candles", indicator, candles.len());
+                program_state.computed_indicators.insert((agent_id, trading_pair.clone(), timeframe.clone(), indicator.clone()), state);
+            }
+        }
+    }
+
+    if skipped_targets > 0 && seeded_targets == 0 {
+        msg!("No pair/timeframe has the {} usable candles required", config.min_history);
+        return Err(MarketError::InsufficientHistory.into());
+    }
+    Ok(())
+}
+
+// Logs a seeded indicator with the candle it was last computed on. The indicator
+// lags when that candle is older than the newest stored one, e.g. after a late or
+// corrected candle (which can't be applied incrementally) or a skipped illiquid one.
+fn get_indicator(
+    program_state: &ProgramState,
+    agent_id: u32,
+    trading_pair: String,
+    timeframe: TimeFrame,
+    indicator: String,
+) -> ProgramResult {
+    let state = match program_state.computed_indicators.get(&(agent_id, trading_pair.clone(), timeframe.clone(), indicator.clone())) {
+        Some(state) => state,
+        None => {
+            msg!("Indicator {} is not seeded for {} by agent {}", indicator, trading_pair, agent_id);
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+    let latest_candle_ts = program_state.latest_candles.get(&(trading_pair.clone(), timeframe.clone())).copied().unwrap_or(0);
+
+    let snapshot = IndicatorSnapshot {
+        value: state.value,
+        decimals: state.decimals,
+        computed_at_candle_ts: state.last_timestamp,
+        latest_candle_ts,
+        stale: state.last_timestamp != latest_candle_ts,
+    };
+    if snapshot.stale {
+        msg!("{} was computed at {}, the latest candle is {}", indicator, state.last_timestamp, latest_candle_ts);
+    }
+    sol_log_data(&[&snapshot.try_to_vec()?]);
+    Ok(())
+}
+
+// Logs the last `limit` (timestamp, value) pairs a seeded indicator retained, oldest
+// first, followed by their decimals. Indicators of agents without
+// `indicator_history_len` keep no history and log an empty series.
+fn get_indicator_history(
+    program_state: &ProgramState,
+    agent_id: u32,
+    trading_pair: String,
+    timeframe: TimeFrame,
+    indicator: String,
+    limit: u32,
+) -> ProgramResult {
+    let state = match program_state.computed_indicators.get(&(agent_id, trading_pair.clone(), timeframe.clone(), indicator.clone())) {
+        Some(state) => state,
+        None => {
+            msg!("Indicator {} is not seeded for {} by agent {}", indicator, trading_pair, agent_id);
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+
+    let series: Vec<(u64, i64)> = state.history.iter()
+        .skip(state.history.len().saturating_sub(limit as usize))
+        .copied()
+        .collect();
+    msg!("Returning {} of {} retained values of {}", series.len(), state.history.len(), indicator);
+    sol_log_data(&[&series.try_to_vec()?, &[state.decimals]]);
+    Ok(())
+}
+
+// Logs (index, signal) for every signal at or above `min_severity`. The index is
+// what AckSignal expects.
+fn get_signals(program_state: &ProgramState, min_severity: u8) -> ProgramResult {
+    let signals: Vec<(u32, &Signal)> = program_state.signals.iter().enumerate()
+        .filter(|(_, signal)| signal.severity >= min_severity)
+        .map(|(index, signal)| (index as u32, signal))
+        .collect();
+
+    msg!("Returning {} of {} signals", signals.len(), program_state.signals.len());
+    sol_log_data(&[&signals.try_to_vec()?]);
+    Ok(())
+}
+
+// Appends a signal unless the agent already emitted the same type for the same pair
+// less than `cooldown` seconds earlier, measured on the signals' candle timestamps
+fn push_signal(
+    signals: &mut Vec<Signal>,
+    last_signal_times: &mut HashMap<(u32, String, String), u64>,
+    cooldown: u64,
+    signal: Signal,
+) {
+    let key = (signal.agent_id, signal.trading_pair.clone(), signal.signal_type.clone());
+    if let Some(last) = last_signal_times.get(&key) {
+        if cooldown > 0 && signal.timestamp < last.saturating_add(cooldown) {
+            msg!("Throttled {} at {}", signal.signal_type, signal.timestamp);
+            return;
+        }
+    }
+    last_signal_times.insert(key, signal.timestamp);
+    signals.push(signal);
+}
+
+// Maps how far a value went past its threshold onto 0..=100, relative to `range`,
+// the distance at which a signal counts as maximally severe
+fn signal_severity(excess: f64, range: f64) -> u8 {
+    if range <= 0.0 {
+        return 100;
+    }
+    (excess / range * 100.0).clamp(0.0, 100.0).round() as u8
+}
+
+// Scales a computed value to the integer stored in indicator state, signals and
+// reports, round(value * 10^decimals). Undefined or out-of-range results fail
+// instead of propagating as NaN or infinity.
+pub fn to_fixed(value: f64, decimals: u8) -> Result<i64, ProgramError> {
+    let scaled = (value * 10f64.powi(decimals as i32)).round();
+    if !scaled.is_finite() || scaled.abs() >= i64::MAX as f64 {
+        msg!("Value {} is not representable with {} decimals", value, decimals);
+        return Err(MarketError::UnrepresentableValue.into());
+    }
+    Ok(scaled as i64)
+}
+
+// Inverse of to_fixed, for clients reading indicator and signal values
+pub fn from_fixed(value: i64, decimals: u8) -> f64 {
+    value as f64 / 10f64.powi(decimals as i32)
+}
+
+fn output_decimals(config: &AgentConfig) -> u8 {
+    if config.output_decimals == 0 {
+        DEFAULT_OUTPUT_DECIMALS
+    } else {
+        config.output_decimals.min(MAX_OUTPUT_DECIMALS)
+    }
+}
+
+// Flags a signal as handled. Signals stay in place so indexes remain stable
+// until ClearAckedSignals purges them.
+fn ack_signal(
+    program_state: &mut ProgramState,
+    index: u32,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    if program_state.signals.len() <= index as usize {
+        msg!("Signal not found");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let agent_id = program_state.signals[index as usize].agent_id;
+    verify_owner(&program_state.agent_configs[agent_id as usize], accounts)?;
+
+    program_state.signals[index as usize].acknowledged = true;
+    msg!("Acknowledged signal {}", index);
+    Ok(())
+}
+
+fn clear_acked_signals(
+    program_state: &mut ProgramState,
+    agent_id: u32,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    // Check if agent exists
+    if program_state.agent_configs.len() <= agent_id as usize {
+        msg!("Agent not found");
+        return Err(ProgramError::InvalidArgument);
+    }
+    verify_owner(&program_state.agent_configs[agent_id as usize], accounts)?;
+
+    let signal_count = program_state.signals.len();
+    program_state.signals.retain(|signal| signal.agent_id != agent_id || !signal.acknowledged);
+    let cleared = (signal_count - program_state.signals.len()) as u32;
+
+    sol_log_data(&[&cleared.to_le_bytes()]);
+    msg!("Cleared {} acknowledged signals", cleared);
+    Ok(())
+}
+
+fn max_indicator_period(config: &AgentConfig) -> u32 {
+    if config.max_indicator_period == 0 {
+        MAX_INDICATOR_PERIOD
+    } else {
+        config.max_indicator_period.min(MAX_INDICATOR_PERIOD)
+    }
+}
+
+// Scans the stored candles of a pair/timeframe for missing intervals and logs the
+// gaps, followed by the timestamps of anomalous candles: ones closer to their
+// predecessor than the timeframe spacing or off its grid, which only happens when
+// candles arrive out of order or from an inconsistent feed.
+fn detect_gaps(
+    program_state: &mut ProgramState,
+    trading_pair: String,
+    timeframe: TimeFrame,
+) -> ProgramResult {
+    let spacing = timeframe.seconds();
+    let candles = candle_series(program_state, &trading_pair, &timeframe);
+
+    let mut gaps = Vec::new();
+    let mut anomalies = Vec::new();
+    for pair in candles.windows(2) {
+        let (previous, current) = (pair[0].timestamp, pair[1].timestamp);
+        let delta = current - previous;
+        if delta % spacing != 0 {
+            anomalies.push(current);
+        } else if delta > spacing {
+            gaps.push(CandleGap { after_ts: previous, before_ts: current, missing: delta / spacing - 1 });
+        }
+    }
+
+    msg!("Found {} gaps and {} anomalies in {} candles", gaps.len(), anomalies.len(), candles.len());
+    sol_log_data(&[&gaps.try_to_vec()?, &anomalies.try_to_vec()?]);
+
+    program_state.gap_counts.insert((trading_pair, timeframe), gaps.len() as u32);
+    Ok(())
+}
+
+// Drops the candles of a pair/timeframe older than the retention of the agents
+// tracking it. Candles are shared, so the longest `max_candle_age` among them wins,
+// and nothing is pruned if any of them keeps candles forever or none tracks the pair.
+// Seeded indicators keep their state, a reseed only sees the remaining candles.
+fn prune_old_candles(
+    program_state: &mut ProgramState,
+    trading_pair: String,
+    timeframe: TimeFrame,
+) -> ProgramResult {
+    let retention: Vec<u64> = program_state.agent_configs.iter()
+        .filter(|config| config.timeframes.contains(&timeframe))
+        .filter(|config| config.pair_indicators.iter().any(|(pair, _)| pair == &trading_pair))
+        .map(|config| config.max_candle_age)
+        .collect();
+    let max_age = match retention.iter().copied().max() {
+        Some(max_age) if !retention.contains(&0) => max_age,
+        _ => {
+            msg!("No retention policy applies to {}, nothing pruned", trading_pair);
+            return Ok(());
+        }
+    };
+
+    let current_time = solana_program::sysvar::clock::Clock::get()?.unix_timestamp as u64;
+    let cutoff = current_time.saturating_sub(max_age);
+    let before = program_state.market_data.len();
+    program_state.market_data.retain(|(pair, tf, timestamp), _| {
+        pair != &trading_pair || tf != &timeframe || *timestamp >= cutoff
+    });
+    let pruned = before - program_state.market_data.len();
+
+    // The newest candle may be gone too if the feed stalled for longer than the retention
+    let key = (trading_pair.clone(), timeframe.clone());
+    if program_state.latest_candles.get(&key).map_or(false, |latest| *latest < cutoff) {
+        program_state.latest_candles.remove(&key);
+    }
+
+    msg!("Pruned {} candles of {} older than {}", pruned, trading_pair, cutoff);
+    Ok(())
+}
+
+// Fills gaps of at most `max_backfill_candles` missing candles with synthesized,
+// zero volume candles flagged as backfilled. Longer gaps are left alone so an
+// outage is never papered over. Indicators need a reseed to pick the new candles up.
+fn backfill_gaps(
+    program_state: &mut ProgramState,
+    agent_id: u32,
+    trading_pair: String,
+    timeframe: TimeFrame,
+    method: BackfillMethod,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    // Check if agent exists
+    if program_state.agent_configs.len() <= agent_id as usize {
+        msg!("Agent not found");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let config = &program_state.agent_configs[agent_id as usize];
+    verify_owner(config, accounts)?;
+    if config.max_backfill_candles == 0 {
+        msg!("Backfilling is disabled for this agent");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let max_missing = config.max_backfill_candles as u64;
+
+    let spacing = timeframe.seconds();
+    let mut filled = Vec::new();
+    let mut skipped_gaps = 0;
+    for pair in candle_series(program_state, &trading_pair, &timeframe).windows(2) {
+        let (previous, next) = (pair[0], pair[1]);
+        let delta = next.timestamp - previous.timestamp;
+        if delta <= spacing || delta % spacing != 0 {
+            continue;
+        }
+        let missing = delta / spacing - 1;
+        if missing > max_missing {
+            skipped_gaps += 1;
+            continue;
+        }
+
+        let mut open = previous.close;
+        for step in 1..=missing {
+            let close = match method {
+                BackfillMethod::ForwardFill => previous.close,
+                BackfillMethod::Linear => previous.close + (next.close - previous.close) * step as f64 / (missing + 1) as f64,
+            };
+            filled.push(MarketData {
+                timestamp: previous.timestamp + step * spacing,
+                open,
+                high: open.max(close),
+                low: open.min(close),
+                close,
+                volume: 0.0,
+                backfilled: true,
+            });
+            open = close;
+        }
+    }
+
+    msg!("Backfilled {} candles, skipped {} gaps longer than {} candles", filled.len(), skipped_gaps, max_missing);
+    for candle in filled {
+        program_state.market_data.insert((trading_pair.clone(), timeframe.clone(), candle.timestamp), candle);
+    }
+    Ok(())
+}
+
+// Evaluates the long trailing stop against the latest candle. The level follows
+// close - k * ATR upwards but never down, and the first close below it records a
+// single "StopHit" signal and freezes the stop until ResetTrailingStop.
+fn update_trailing_stop(
+    program_state: &mut ProgramState,
+    agent_id: u32,
+    trading_pair: String,
+    timeframe: TimeFrame,
+) -> ProgramResult {
+    // Check if agent exists
+    if program_state.agent_configs.len() <= agent_id as usize {
+        msg!("Agent not found");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let config = &program_state.agent_configs[agent_id as usize];
+    let atr_period = match config.atr_period {
+        0 => DEFAULT_ATR_PERIOD,
+        atr_period => atr_period,
+    } as usize;
+    let atr_multiplier = config.atr_multiplier;
+
+    let candles = candle_series(program_state, &trading_pair, &timeframe);
+    let atr = match average_true_range(&candles, atr_period) {
+        Some(atr) => atr,
+        None => {
+            msg!("ATR_{} needs {} candles, {} stored", atr_period, atr_period + 1, candles.len());
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+    let latest = candles[candles.len() - 1];
+    let (close, timestamp) = (latest.close, latest.timestamp);
+
+    let key = (agent_id, trading_pair.clone(), timeframe.clone());
+    let candidate = close - atr_multiplier * atr;
+    let stop = program_state.trailing_stops.entry(key).or_insert(TrailingStop { level: candidate, ..TrailingStop::default() });
+    if stop.triggered || timestamp <= stop.last_timestamp {
+        msg!("Trailing stop already evaluated up to {}", stop.last_timestamp);
+        return Ok(());
+    }
+    stop.last_timestamp = timestamp;
+
+    if close < stop.level {
+        stop.triggered = true;
+        msg!("Close {} breached the trailing stop at {}", close, stop.level);
+        let severity = signal_severity(stop.level - close, atr_multiplier * atr);
+        let decimals = output_decimals(&program_state.agent_configs[agent_id as usize]);
+        let cooldown = program_state.agent_configs[agent_id as usize].signal_cooldown;
+        push_signal(&mut program_state.signals, &mut program_state.last_signal_times, cooldown, Signal {
+            agent_id,
+            trading_pair,
+            timeframe,
+            signal_type: "StopHit".to_string(),
+            timestamp,
+            acknowledged: false,
+            severity,
+            value: to_fixed(close, decimals)?,
+            decimals,
+        });
+        return Ok(());
+    }
+
+    stop.level = stop.level.max(candidate);
+    msg!("Trailing stop at {} (ATR {})", stop.level, atr);
+    Ok(())
+}
+
+// Drops a tracked stop, typically after a triggered stop once a new position is opened
+fn reset_trailing_stop(
+    program_state: &mut ProgramState,
+    agent_id: u32,
+    trading_pair: String,
+    timeframe: TimeFrame,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    // Check if agent exists
+    if program_state.agent_configs.len() <= agent_id as usize {
+        msg!("Agent not found");
+        return Err(ProgramError::InvalidArgument);
+    }
+    verify_owner(&program_state.agent_configs[agent_id as usize], accounts)?;
+
+    if program_state.trailing_stops.remove(&(agent_id, trading_pair, timeframe)).is_none() {
+        msg!("No trailing stop tracked for this pair");
+        return Err(ProgramError::InvalidArgument);
+    }
+    Ok(())
+}
+
+// Simple average of the true range over the last `period` candles, where the true
+// range is max(high - low, |high - previous close|, |low - previous close|)
+fn average_true_range(candles: &[&MarketData], period: usize) -> Option<f64> {
+    if period == 0 || candles.len() < period + 1 {
+        return None;
+    }
+    let recent = &candles[candles.len() - period - 1..];
+    let total: f64 = recent.windows(2)
+        .map(|pair| {
+            let (previous_close, candle) = (pair[0].close, pair[1]);
+            (candle.high - candle.low)
+                .max((candle.high - previous_close).abs())
+                .max((candle.low - previous_close).abs())
+        })
+        .sum();
+    Some(total / period as f64)
+}
+
+// Maximum drawdown over the last `window` candles (0 uses every stored candle),
+// tracking the running peak close and the largest decline from it
+fn compute_drawdown(
+    program_state: &ProgramState,
+    trading_pair: String,
+    timeframe: TimeFrame,
+    window: u32,
+) -> ProgramResult {
+    let candles = candle_series(program_state, &trading_pair, &timeframe);
+    if candles.is_empty() {
+        msg!("No candles stored for {}", trading_pair);
+        return Err(ProgramError::InvalidArgument);
+    }
+    let start = match window {
+        0 => 0,
+        window => candles.len().saturating_sub(window as usize),
+    };
+
+    let mut peak = candles[start];
+    let mut max_drawdown_pct = 0.0;
+    let (mut peak_ts, mut trough_ts) = (None, None);
+    for candle in &candles[start..] {
+        if candle.close > peak.close {
+            peak = candle;
+            continue;
+        }
+        if peak.close <= 0.0 {
+            continue;
+        }
+        let drawdown_pct = (peak.close - candle.close) / peak.close * 100.0;
+        if drawdown_pct > max_drawdown_pct {
+            max_drawdown_pct = drawdown_pct;
+            peak_ts = Some(peak.timestamp);
+            trough_ts = Some(candle.timestamp);
+        }
+    }
+
+    msg!("Max drawdown of {} over {} candles: {}%", trading_pair, candles.len() - start, max_drawdown_pct);
+    let report = DrawdownReport {
+        max_drawdown_pct: to_fixed(max_drawdown_pct, DEFAULT_OUTPUT_DECIMALS)?,
+        decimals: DEFAULT_OUTPUT_DECIMALS,
+        peak_ts,
+        trough_ts,
+    };
+    sol_log_data(&[&report.try_to_vec()?]);
+    Ok(())
+}
+
+// Sharpe-like ratio of the last `window` close-to-close returns: (mean return minus
+// the per-candle risk-free rate `risk_free_bps`) over their population standard
+// deviation, the same dispersion the STDDEV indicator reports. Flat closes have no
+// deviation and fail rather than reporting an infinite ratio.
+fn compute_sharpe(
+    program_state: &ProgramState,
+    trading_pair: String,
+    timeframe: TimeFrame,
+    window: u32,
+    risk_free_bps: i64,
+) -> ProgramResult {
+    if window < 2 {
+        msg!("Sharpe window must be at least 2");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let candles = candle_series(program_state, &trading_pair, &timeframe);
+    let window = window as usize;
+    if candles.len() < window + 1 {
+        msg!("Only {} candles stored, {} required", candles.len(), window + 1);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let recent = &candles[candles.len() - window - 1..];
+    let mut returns = Vec::with_capacity(window);
+    for pair in recent.windows(2) {
+        if pair[0].close == 0.0 {
+            msg!("Zero close at {}, returns are undefined", pair[0].timestamp);
+            return Err(ProgramError::InvalidArgument);
+        }
+        returns.push(pair[1].close / pair[0].close - 1.0);
+    }
+
+    let mean = returns.iter().sum::<f64>() / window as f64;
+    let variance = returns.iter().map(|r| (r - mean) * (r - mean)).sum::<f64>() / window as f64;
+    if variance == 0.0 {
+        msg!("Returns have no variance, the Sharpe ratio is undefined");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let sharpe = (mean - risk_free_bps as f64 / 10_000.0) / variance.sqrt();
+
+    msg!("Sharpe ratio of {} over {} returns: {}", trading_pair, window, sharpe);
+    let report = SharpeReport {
+        sharpe: to_fixed(sharpe, DEFAULT_OUTPUT_DECIMALS)?,
+        decimals: DEFAULT_OUTPUT_DECIMALS,
+        returns: window as u32,
+        end_ts: recent[recent.len() - 1].timestamp,
+    };
+    sol_log_data(&[&report.try_to_vec()?]);
+    Ok(())
+}
+
+// Raises a "Volume Spike" signal when the latest candle's volume exceeds
+// `multiplier` times the average of the `window` candles before it. The signal's
+// value is the spike magnitude, latest volume / average volume.
+fn detect_volume_spike(
+    program_state: &mut ProgramState,
+    agent_id: u32,
+    trading_pair: String,
+    timeframe: TimeFrame,
+    window: u32,
+    multiplier: f64,
+) -> ProgramResult {
+    // Check if agent exists
+    if program_state.agent_configs.len() <= agent_id as usize {
+        msg!("Agent not found");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if window == 0 || multiplier <= 0.0 {
+        msg!("Window and multiplier must be positive");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let candles = candle_series(program_state, &trading_pair, &timeframe);
+    let window = window as usize;
+    if candles.len() < window + 1 {
+        msg!("Only {} candles stored, {} needed, skipping", candles.len(), window + 1);
+        return Ok(());
+    }
+
+    let latest = candles[candles.len() - 1];
+    let preceding = &candles[candles.len() - window - 1..candles.len() - 1];
+    let average_volume = preceding.iter().map(|data| data.volume).sum::<f64>() / window as f64;
+    if average_volume <= 0.0 {
+        msg!("No volume in the preceding {} candles, skipping", window);
+        return Ok(());
+    }
+
+    let ratio = latest.volume / average_volume;
+    msg!("Volume of candle {} is {}x the {} candle average", latest.timestamp, ratio, window);
+    if ratio > multiplier {
+        let timestamp = latest.timestamp;
+        let decimals = output_decimals(&program_state.agent_configs[agent_id as usize]);
+        let cooldown = program_state.agent_configs[agent_id as usize].signal_cooldown;
+        push_signal(&mut program_state.signals, &mut program_state.last_signal_times, cooldown, Signal {
+            agent_id,
+            trading_pair,
+            timeframe,
+            signal_type: "Volume Spike".to_string(),
+            timestamp,
+            acknowledged: false,
+            severity: signal_severity(ratio - multiplier, multiplier),
+            value: to_fixed(ratio, decimals)?,
+            decimals,
+        });
+    }
+    Ok(())
+}
+
+// Pearson correlation of the two pairs' close-to-close returns over the last
+// `window` returns. Only timestamps present for both pairs are used.
+fn compute_correlation(
+    program_state: &mut ProgramState,
+    agent_id: u32,
+    pair_a: String,
+    pair_b: String,
+    timeframe: TimeFrame,
+    window: u32,
+) -> ProgramResult {
+    // Check if agent exists
+    if program_state.agent_configs.len() <= agent_id as usize {
+        msg!("Agent not found");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if window < 2 {
+        msg!("Correlation window must be at least 2");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // Align both series on their common timestamps
+    let closes_b: BTreeMap<u64, f64> = candle_series(program_state, &pair_b, &timeframe).iter().map(|data| (data.timestamp, data.close)).collect();
+    let aligned: Vec<(u64, f64, f64)> = candle_series(program_state, &pair_a, &timeframe).iter()
+        .filter_map(|data| closes_b.get(&data.timestamp).map(|close_b| (data.timestamp, data.close, *close_b)))
+        .collect();
+
+    let window = window as usize;
+    if aligned.len() < window + 1 {
+        msg!("Only {} overlapping candles, {} required", aligned.len(), window + 1);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let recent = &aligned[aligned.len() - window - 1..];
+    let mut returns_a = Vec::with_capacity(window);
+    let mut returns_b = Vec::with_capacity(window);
+    for pair in recent.windows(2) {
+        if pair[0].1 == 0.0 || pair[0].2 == 0.0 {
+            msg!("Zero close at {}, returns are undefined", pair[0].0);
+            return Err(ProgramError::InvalidArgument);
+        }
+        returns_a.push(pair[1].1 / pair[0].1 - 1.0);
+        returns_b.push(pair[1].2 / pair[0].2 - 1.0);
+    }
+
+    let correlation = match pearson_correlation(&returns_a, &returns_b) {
+        Some(correlation) => correlation,
+        None => {
+            msg!("Returns have no variance, correlation is undefined");
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+    msg!("Correlation of {} and {}: {}", pair_a, pair_b, correlation);
+
+    let threshold = program_state.agent_configs[agent_id as usize].correlation_threshold;
+    if correlation < threshold {
+        let decimals = output_decimals(&program_state.agent_configs[agent_id as usize]);
+        let cooldown = program_state.agent_configs[agent_id as usize].signal_cooldown;
+        push_signal(&mut program_state.signals, &mut program_state.last_signal_times, cooldown, Signal {
+            agent_id,
+            trading_pair: format!("{} vs {}", pair_a, pair_b),
+            timeframe,
+            signal_type: "Correlation Breakdown".to_string(),
+            timestamp: recent[recent.len() - 1].0,
+            acknowledged: false,
+            severity: signal_severity(threshold - correlation, threshold + 1.0),
+            value: to_fixed(correlation, decimals)?,
+            decimals,
+        });
+    }
+
+    Ok(())
+}
+
+fn pearson_correlation(xs: &[f64], ys: &[f64]) -> Option<f64> {
+    let n = xs.len() as f64;
+    let mean_x = xs.iter().sum::<f64>() / n;
+    let mean_y = ys.iter().sum::<f64>() / n;
+
+    let mut covariance = 0.0;
+    let mut variance_x = 0.0;
+    let mut variance_y = 0.0;
+    for (x, y) in xs.iter().zip(ys.iter()) {
+        covariance += (x - mean_x) * (y - mean_y);
+        variance_x += (x - mean_x) * (x - mean_x);
+        variance_y += (y - mean_y) * (y - mean_y);
+    }
+
+    if variance_x == 0.0 || variance_y == 0.0 {
+        return None;
+    }
+    Some(covariance / (variance_x * variance_y).sqrt())
+}
+
+// Stored candles for a pair and timeframe, oldest first
+fn candle_series<'a>(program_state: &'a ProgramState, trading_pair: &str, timeframe: &TimeFrame) -> Vec<&'a MarketData> {
+    let mut candles = program_state.market_data.iter()
+        .filter(|((pair, tf, _), _)| pair == trading_pair && tf == timeframe)
+        .map(|(_, data)| data)
+        .collect::<Vec<_>>();
+    candles.sort_by_key(|data| data.timestamp);
+    candles
+}
+
+// Parses an indicator string such as "SMA_20" into its kind and period.
+// Supported kinds are SMA, RSI, ROC (rate of change), STDDEV (standard deviation
+// of returns, which needs a period of at least 2) and WILLR (Williams %R).
+fn parse_indicator(indicator: &str) -> Option<(&str, usize)> {
+    let (kind, period) = indicator.split_once('_')?;
+    let period = period.parse::<usize>().ok()?;
+    if period == 0 || !["SMA", "RSI", "ROC", "STDDEV", "WILLR"].contains(&kind) || (kind == "STDDEV" && period < 2) {
+        return None;
+    }
+    Some((kind, period))
+}
+
+// Applies a single candle to the rolling state, in O(1) except for STDDEV which
+// revisits its `period` returns
+fn apply_candle(indicator: &str, state: &mut IndicatorState, candle: &MarketData) -> ProgramResult {
+    let period = state.period as usize;
+    let (price, timestamp) = (state.price_source.price(candle), candle.timestamp);
+
+    match parse_indicator(indicator) {
+        Some(("SMA", _)) => {
+            state.window.push_back(price);
+            state.running_sum += price;
+            if state.window.len() > period {
+                state.running_sum -= state.window.pop_front().unwrap();
+            }
+            if state.window.len() == period {
+                state.value = Some(to_fixed(state.running_sum / period as f64, state.decimals)?);
+            }
+        }
+        Some(("RSI", _)) => {
+            if state.candles_seen > 0 {
+                let change = price - state.last_price;
+                let gain = change.max(0.0);
+                let loss = (-change).max(0.0);
+                let changes_seen = state.candles_seen as usize;
+
+                if changes_seen <= period {
+                    // The first `period` changes seed the averages with a simple mean
+                    state.avg_gain += gain / period as f64;
+                    state.avg_loss += loss / period as f64;
+                } else {
+                    // Wilder smoothing afterwards
+                    state.avg_gain = (state.avg_gain * (period - 1) as f64 + gain) / period as f64;
+                    state.avg_loss = (state.avg_loss * (period - 1) as f64 + loss) / period as f64;
+                }
+
+                if changes_seen >= period {
+                    let rsi = if state.avg_loss == 0.0 {
+                        100.0
+                    } else {
+                        100.0 - 100.0 / (1.0 + state.avg_gain / state.avg_loss)
+                    };
+                    state.value = Some(to_fixed(rsi, state.decimals)?);
+                }
+            }
+        }
+        Some(("ROC", _)) => {
+            // (price - price_n_ago) / price_n_ago * 100
+            state.window.push_back(price);
+            if state.window.len() > period + 1 {
+                state.window.pop_front();
+            }
+            if state.window.len() == period + 1 {
+                // A zero price leaves the value undefined until it leaves the window,
+                // the candle itself is still applied
+                let price_n_ago = state.window[0];
+                if price_n_ago == 0.0 {
+                    msg!("{} is undefined for a zero price {} candles ago", indicator, period);
+                    state.value = None;
+                } else {
+                    state.value = Some(to_fixed((price - price_n_ago) / price_n_ago * 100.0, state.decimals)?);
+                }
+            }
+        }
+        Some(("STDDEV", _)) => {
+            // Population standard deviation of the last `period` price-to-price returns, in percent
+            state.window.push_back(price);
+            if state.window.len() > period + 1 {
+                state.window.pop_front();
+            }
+            if state.window.len() == period + 1 {
+                // Same as ROC, a zero price in the window leaves the value undefined
+                if state.window.iter().take(period).any(|previous| *previous == 0.0) {
+                    msg!("{} is undefined for a zero price in its window", indicator);
+                    state.value = None;
+                } else {
+                    let returns: Vec<f64> = state.window.iter().zip(state.window.iter().skip(1))
+                        .map(|(previous, current)| (current - previous) / previous * 100.0)
+                        .collect();
+                    let mean = returns.iter().sum::<f64>() / period as f64;
+                    let variance = returns.iter().map(|r| (r - mean) * (r - mean)).sum::<f64>() / period as f64;
+                    state.value = Some(to_fixed(variance.sqrt(), state.decimals)?);
+                }
+            }
+        }
+        Some(("WILLR", _)) => {
+            // -100 * (highest high - close) / (highest high - lowest low) over the last `period`
+            // candles, always on the close whatever the price source. A flat range has no
+            // position within it and reports the midpoint, -50.
+            state.highs.push_back(candle.high);
+            state.lows.push_back(candle.low);
+            if state.highs.len() > period {
+                state.highs.pop_front();
+                state.lows.pop_front();
+            }
+            if state.highs.len() == period {
+                let highest_high = state.highs.iter().copied().fold(f64::MIN, f64::max);
+                let lowest_low = state.lows.iter().copied().fold(f64::MAX, f64::min);
+                let range = highest_high - lowest_low;
+                let willr = if range > 0.0 { -100.0 * (highest_high - candle.close) / range } else { -50.0 };
+                state.value = Some(to_fixed(willr, state.decimals)?);
+            }
+        }
+        _ => {}
+    }
+
+    state.candles_seen += 1;
+    state.last_price = price;
+    state.last_timestamp = timestamp;
+    if let Some(value) = state.value.filter(|_| state.history_len > 0) {
+        state.history.push_back((timestamp, value));
+        if state.history.len() > state.history_len as usize {
+            state.history.pop_front();
+        }
+    }
+    Ok(())
+}
+
+// Reads the state account, upgrading accounts written with the single-pair layout
+// instead of silently replacing them with a default state. Data matching neither
+// layout is an error.
+fn load_program_state(data: &[u8]) -> Result<ProgramState, ProgramError> {
+    // A freshly allocated account is all zeroes
+    if data.iter().all(|byte| *byte == 0) {
+        return Ok(ProgramState { version: STATE_VERSION, ..ProgramState::default() });
+    }
+
+    // `deserialize` tolerates the zero padding after the serialized state
+    if data[0] == STATE_VERSION {
+        if let Ok(program_state) = ProgramState::deserialize(&mut &data[..]) {
+            return Ok(program_state);
+        }
+    }
+
+    // Accounts written before the version byte existed
+    if let Ok(legacy_state) = LegacyProgramState::deserialize(&mut &data[..]) {
+        msg!("Migrating program state to version {}", STATE_VERSION);
+        return Ok(legacy_state.into());
+    }
+
+    msg!("Unknown program state version: {}", data[0]);
+    Err(ProgramError::InvalidAccountData)
+}
+
+#[derive(BorshDeserialize)]
+struct LegacyMarketData {
+    timestamp: u64,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume: f64,
+}
+
+#[derive(BorshDeserialize)]
+struct LegacyAgentInstance {
+    agent_id: u32,
+    status: u8,
+    start_time: u64,
+}
+
+#[derive(BorshDeserialize)]
+struct LegacyProgramState {
+    next_agent_id: u32,
+    agent_configs: Vec<SinglePairAgentConfig>,
+    agent_instances: Vec<LegacyAgentInstance>,
+    market_data: HashMap<(String, TimeFrame, u64), LegacyMarketData>,
+}
+
+impl From<LegacyProgramState> for ProgramState {
+    fn from(legacy: LegacyProgramState) -> Self {
+        let agent_configs: Vec<AgentConfig> = legacy.agent_configs.into_iter().map(AgentConfig::from).collect();
+
+        // Legacy instance ids were their position in the vector
+        let agent_instances: Vec<AgentInstance> = legacy.agent_instances.into_iter().enumerate().map(|(index, instance)| AgentInstance {
+            id: index as u32,
+            agent_id: instance.agent_id,
+            status: instance.status,
+            start_time: instance.start_time,
+            last_heartbeat: instance.start_time,
+        }).collect();
+
+        let market_data: HashMap<(String, TimeFrame, u64), MarketData> = legacy.market_data.into_iter().map(|(key, data)| (key, MarketData {
+            timestamp: data.timestamp,
+            open: data.open,
+            high: data.high,
+            low: data.low,
+            close: data.close,
+            volume: data.volume,
+            backfilled: false,
+        })).collect();
+        let mut latest_candles: HashMap<(String, TimeFrame), u64> = HashMap::new();
+        for (pair, timeframe, timestamp) in market_data.keys() {
+            let latest = latest_candles.entry((pair.clone(), timeframe.clone())).or_insert(*timestamp);
+            *latest = (*latest).max(*timestamp);
+        }
+
+        // Legacy accounts were in use already, the first agent's owner becomes the state owner
+        let owner = agent_configs.first().map(|config| config.owner).unwrap_or_default();
+
+        ProgramState {
+            version: STATE_VERSION,
+            is_initialized: true,
+            owner,
+            next_agent_id: legacy.next_agent_id,
+            next_instance_id: agent_instances.len() as u32,
+            agent_configs,
+            agent_instances,
+            market_data,
+            latest_candles,
+            ..ProgramState::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAIR: &str = "SOL/USDC";
+
+    fn config(owner: Pubkey, indicators: &[&str]) -> AgentConfig {
+        SinglePairAgentConfig {
+            owner,
+            description: "test agent".to_string(),
+            trading_pair: PAIR.to_string(),
+            timeframes: vec![TimeFrame::OneMinute],
+            indicators: indicators.iter().map(|indicator| indicator.to_string()).collect(),
+        }.into()
+    }
+
+    fn candle(timestamp: u64, close: f64, volume: f64) -> MarketData {
+        MarketData { timestamp, open: close, high: close, low: close, close, volume, ..MarketData::default() }
+    }
+
+    fn state_with_candles(config: AgentConfig, candles: &[MarketData]) -> ProgramState {
+        let mut program_state = ProgramState::default();
+        program_state.agent_configs.push(config);
+        for data in candles {
+            program_state.market_data.insert((PAIR.to_string(), TimeFrame::OneMinute, data.timestamp), data.clone());
+        }
+        program_state
+    }
+
+    fn seeded_value(program_state: &ProgramState, indicator: &str) -> Option<f64> {
+        let state = program_state.computed_indicators.get(&(0, PAIR.to_string(), TimeFrame::OneMinute, indicator.to_string()))?;
+        state.value.map(|value| from_fixed(value, state.decimals))
+    }
+
+    #[test]
+    fn seeding_skips_interleaved_low_volume_candles() {
+        let owner = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+        let (mut lamports, mut data) = (0u64, Vec::<u8>::new());
+        let accounts = [AccountInfo::new(&owner, true, false, &mut lamports, &mut data, &program_id, false, 0)];
+        let candles = [
+            candle(60, 10.0, 100.0),
+            candle(120, 1000.0, 1.0),
+            candle(180, 20.0, 100.0),
+            candle(240, 2000.0, 1.0),
+            candle(300, 30.0, 100.0),
+        ];
+
+        let mut config = config(owner, &["SMA_3", "SMA_4"]);
+        config.min_candle_volume = 50.0;
+        let mut program_state = state_with_candles(config, &candles);
+        seed_indicators(&mut program_state, 0, &accounts).unwrap();
+
+        // Only the three liquid closes feed the average
+        assert_eq!(seeded_value(&program_state, "SMA_3"), Some(20.0));
+        // Five candles are stored but only three are usable, so the window never fills
+        assert!(!program_state.computed_indicators.contains_key(&(0, PAIR.to_string(), TimeFrame::OneMinute, "SMA_4".to_string())));
+        assert!(get_indicator(&program_state, 0, PAIR.to_string(), TimeFrame::OneMinute, "SMA_4".to_string()).is_err());
+    }
+
+    #[test]
+    fn seeding_without_a_volume_floor_uses_every_candle() {
+        let owner = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+        let (mut lamports, mut data) = (0u64, Vec::<u8>::new());
+        let accounts = [AccountInfo::new(&owner, true, false, &mut lamports, &mut data, &program_id, false, 0)];
+        let candles = [
+            candle(60, 10.0, 100.0),
+            candle(120, 1000.0, 1.0),
+            candle(180, 20.0, 100.0),
+            candle(240, 2000.0, 1.0),
+            candle(300, 30.0, 100.0),
+        ];
+
+        let mut program_state = state_with_candles(config(owner, &["SMA_4"]), &candles);
+        seed_indicators(&mut program_state, 0, &accounts).unwrap();
+
+        assert_eq!(seeded_value(&program_state, "SMA_4"), Some(762.5));
+    }
+
+    #[test]
+    fn seeding_fails_when_no_target_has_the_minimum_history() {
+        let owner = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+        let (mut lamports, mut data) = (0u64, Vec::<u8>::new());
+        let accounts = [AccountInfo::new(&owner, true, false, &mut lamports, &mut data, &program_id, false, 0)];
+        let candles = [candle(60, 10.0, 100.0), candle(120, 1000.0, 1.0), candle(180, 20.0, 100.0)];
+
+        let mut config = config(owner, &["SMA_2"]);
+        config.min_candle_volume = 50.0;
+        config.min_history = 3;
+        let mut program_state = state_with_candles(config, &candles);
+
+        assert_eq!(
+            seed_indicators(&mut program_state, 0, &accounts),
+            Err(MarketError::InsufficientHistory.into())
+        );
+        assert!(program_state.computed_indicators.is_empty());
+    }
+
+    fn stddev_after(indicator: &str, closes: &[f64]) -> IndicatorState {
+        let (_, period) = parse_indicator(indicator).unwrap();
+        let mut state = IndicatorState { period: period as u32, decimals: 6, ..IndicatorState::default() };
+        for (index, close) in closes.iter().enumerate() {
+            apply_candle(indicator, &mut state, &candle(60 * (index as u64 + 1), *close, 100.0)).unwrap();
+        }
+        state
+    }
+
+    #[test]
+    fn stddev_of_a_constant_price_is_zero() {
+        assert_eq!(stddev_after("STDDEV_3", &[50.0, 50.0, 50.0, 50.0, 50.0]).value, Some(0));
+    }
+
+    #[test]
+    fn stddev_waits_for_period_plus_one_prices() {
+        assert_eq!(stddev_after("STDDEV_3", &[50.0, 51.0, 52.0]).value, None);
+    }
+
+    #[test]
+    fn stddev_is_taken_over_percent_returns() {
+        // Returns of +10% and -10% average to zero with a deviation of 10
+        assert_eq!(stddev_after("STDDEV_2", &[100.0, 110.0, 99.0]).value, Some(10_000_000));
+    }
+
+    #[test]
+    fn stddev_is_undefined_for_a_zero_price_in_its_window() {
+        assert_eq!(stddev_after("STDDEV_2", &[0.0, 10.0, 20.0]).value, None);
+    }
+
+    #[test]
+    fn stddev_needs_a_period_of_at_least_two() {
+        assert_eq!(parse_indicator("STDDEV_1"), None);
+        assert_eq!(parse_indicator("STDDEV_2"), Some(("STDDEV", 2)));
+
+        let owner = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+        let (mut lamports, mut data) = (0u64, Vec::<u8>::new());
+        let accounts = [AccountInfo::new(&owner, true, false, &mut lamports, &mut data, &program_id, false, 0)];
+        let candles = [candle(60, 10.0, 100.0), candle(120, 11.0, 100.0), candle(180, 12.0, 100.0)];
+        let mut program_state = state_with_candles(config(owner, &["STDDEV_1"]), &candles);
+
+        assert_eq!(seed_indicators(&mut program_state, 0, &accounts), Err(ProgramError::InvalidArgument));
+        assert!(program_state.computed_indicators.is_empty());
+    }
 }
\ No newline at end of file
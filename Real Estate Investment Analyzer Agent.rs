@@ -3,6 +3,8 @@ use solana_program::{
     account_info::{AccountInfo, next_account_info},
     entrypoint,
     entrypoint::ProgramResult,
+    keccak,
+    log::sol_log_data,
     msg,
     program_error::ProgramError,
     pubkey::Pubkey,
@@ -10,6 +12,27 @@ use solana_program::{
 };
 use std::collections::{HashMap};
 
+// Layout version written as the first byte of ProgramState. Bump it whenever a
+// stored struct changes and teach `load_program_state` to migrate the old layout.
+// Version 1 is the layout of the first release to carry the version byte, every
+// change made before that release ships as part of it.
+const STATE_VERSION: u8 = 1;
+
+// Number of market data snapshots kept per area, the oldest is dropped first
+const MAX_MARKET_HISTORY_PER_AREA: usize = 52;
+
+// Largest BatchRegisterProperties call, bounded by compute and account size
+const MAX_PROPERTY_BATCH: usize = 20;
+
+// property_id of opportunities that concern a whole area rather than a single property
+const AREA_OPPORTUNITY_ID: u32 = u32::MAX;
+
+// Maximum number of opportunities returned by a single listing query
+const MAX_OPPORTUNITIES_PER_PAGE: u32 = 25;
+
+// Transactions and opportunities younger than this count towards GetAreaSummary
+const AREA_SUMMARY_MAX_AGE: u64 = 365 * 24 * 60 * 60;
+
 // Property Structure
 #[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default)]
 pub struct Property {
@@ -18,9 +41,18 @@ pub struct Property {
     pub address: String,
     pub size_sqft: u32,
      pub features: Vec<String>,
+    pub financing: Option<Financing>, // Loan used to buy the property, None for an all-cash purchase
     // Add other property details
 }
 
+// Mortgage terms of a leveraged purchase
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default)]
+pub struct Financing {
+    pub loan_amount: u64,       // lamports
+    pub interest_rate_bps: u32, // annual rate, eg: 650 for 6.5%
+    pub term_months: u32,
+}
+
 // Transaction Data (Sale or Rental)
 #[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default)]
 pub struct Transaction {
@@ -39,6 +71,7 @@ pub struct MarketData {
   pub area_name: String,
   pub average_price_sqft: f64,
   pub average_rent_sqft: f64,
+  pub timestamp: u64,
 }
 
 // Opportunity Struct
@@ -48,6 +81,52 @@ pub struct Opportunity {
   pub opportunity_type: String,
   pub timestamp: u64,
   pub additional_info: String,
+  pub roi_bps: Option<i64>, // ROI in basis points for opportunities that evaluated it
+}
+
+// Emitted via sol_log_data once for every opportunity an analysis records, so an
+// indexer can forward new opportunities without diffing the state. Dry runs emit none.
+#[derive(BorshSerialize, Debug, Clone)]
+pub struct OpportunityDetected {
+    pub agent_id: u32,
+    pub property_id: u32,
+    pub opportunity_type: String,
+    pub roi_bps: Option<i64>, // score of the opportunity, None for types that don't evaluate ROI
+    pub timestamp: u64,
+}
+
+// Activity of one counterparty across all recorded transactions, reported by GetCounterpartyStats
+#[derive(BorshSerialize, Debug, Clone, Default)]
+pub struct CounterpartyStats {
+    pub sale_count: u32,    // sales as buyer or seller
+    pub sale_volume: u64,   // lamports
+    pub rental_count: u32,  // rentals as tenant
+    pub rental_volume: u64, // lamports
+}
+
+// Size of the program state, reported by GetStateStats
+#[derive(BorshSerialize, Debug, Clone, Default)]
+pub struct StateStats {
+    pub agent_count: u32,
+    pub instance_count: u32,
+    pub property_count: u32,
+    pub transaction_count: u32,  // across all properties
+    pub opportunity_count: u32,
+    pub market_data_count: u32,  // snapshots across all areas
+    pub serialized_size: u64,    // bytes the state serializes to
+    pub allocated_size: u64,     // bytes allocated for the state account
+}
+
+// Dashboard figures for one area, reported by GetAreaSummary. Every field is 0
+// for an area without data.
+#[derive(BorshSerialize, Debug, Clone, Default)]
+pub struct AreaSummary {
+    pub property_count: u32,
+    pub sale_count: u32,          // recent sales behind the price figures
+    pub average_price_sqft: f64,  // recent sale prices per sqft
+    pub median_price_sqft: f64,
+    pub average_rent_sqft: f64,   // recent rental prices per sqft
+    pub opportunity_count: u32,   // recent opportunities on the area's properties
 }
 
 // Agent Configuration (Real Estate Specific)
@@ -57,31 +136,83 @@ pub struct AgentConfig {
     pub description: String,
      pub target_area: String,
     pub desired_cap_rate: f64,
-     pub min_roi: f64,
+     pub min_roi_bps: i64, // Minimum ROI in basis points, eg: 1500 for 15%
+    pub min_appreciation: f64, // Minimum sale-to-sale price growth, eg: 0.1 for 10%
+    // Strategy toggles, enable all three to keep the original behaviour
+    pub check_cap_rate: bool,
+    pub check_roi: bool,
+    pub check_appreciation: bool,
+    pub max_transaction_age: u64, // Seconds a transaction counts as recent, 0 disables the check
+    pub price_basis: PriceBasis,
+    pub analysis_cooldown: u64, // Minimum seconds between two analyses, 0 disables the cooldown
+    pub composite_mode: bool, // Only flag "Strong Buy" when cap rate, ROI and appreciation all qualify
+    pub rental_window: u32, // Cap rate from the property's last K rentals instead of the area's market rent, 0 uses the market rent
+    // SMA windows over the area's average_price_sqft snapshots. A short average below the
+    // long one flags a "Cooling Market" opportunity, a long window of 0 disables the check.
+    pub short_price_window: u32,
+    pub long_price_window: u32,
+    pub max_properties_per_analysis: u32, // Properties checked per AnalyzeOpportunities call, 0 checks all of them at once
+    pub check_cash_yield: bool,
+    pub min_cash_yield: f64, // Minimum cash-on-cash return for "High Cash Yield", eg: 0.08 for 8%
+    // Multi-sig ownership: with `owners` set, privileged instructions need signatures from
+    // `owner_threshold` of them and `owner` is no longer consulted. Empty keeps `owner` as 1-of-1.
+    pub owners: Vec<Pubkey>,
+    pub owner_threshold: u8,
+    pub max_concurrent_instances: u32, // Instances with status created or running allowed per agent, 0 means no limit
+    pub min_transactions_per_area: u32, // Recent transactions the target area needs before it is analyzed, 0 disables the check
+    // Auto-completion: with the flag set, the next analysis completes running instances whose
+    // triggered opportunity was acknowledged or is older than `opportunity_ttl` seconds (0 never expires)
+    pub auto_complete_instances: bool,
+    pub opportunity_ttl: u64,
     // Add more real estate-specific settings
 }
 
+// Price per sqft used to value properties during analysis
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default, PartialEq)]
+pub enum PriceBasis {
+    #[default]
+    MarketAverage,  // `average_price_sqft` from the area's latest market data
+    WeightedMedian, // size-weighted median of the area's recent sale prices per sqft
+}
+
 // Agent Instance Structure
 #[derive(BorshDeserialize, BorshSerialize, Debug, Clone)]
 pub struct AgentInstance {
+    pub id: u32,
     pub agent_id: u32,
     pub status: u8,         // 0: created, 1: running, 2: completed, 3: error
     pub start_time: u64,
+    pub last_heartbeat: u64, // Time of the worker's last Heartbeat, starts at start_time
     pub triggered_opportunity: Option<Opportunity>,
+    pub filter: Option<InstanceFilter>, // Opportunities this instance triggers on, None triggers on any
+    pub acknowledged: bool, // Set by AckTriggeredOpportunity once the worker has handled the triggered opportunity
+}
+
+// Criteria an opportunity must meet to trigger an instance, unset fields match anything
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default)]
+pub struct InstanceFilter {
+    pub opportunity_type: Option<String>,
+    pub min_roi_bps: Option<i64>, // Opportunities without an ROI never meet it
 }
 
 // Program State
 #[derive(BorshDeserialize, BorshSerialize, Debug, Default)]
 pub struct ProgramState {
+    pub version: u8,
+    pub is_initialized: bool,
+    pub owner: Pubkey,             // Recorded once by Initialize, must sign CreateAgent
     pub next_agent_id: u32,
-    pub next_property_id: u32,
+    pub next_instance_id: u32,     // Counter for instance ids, ids are never reused once an instance is closed
+    pub next_property_id: u32,     // Only ever incremented, ids of deregistered properties are never handed out again
     pub agent_configs: Vec<AgentConfig>,
     pub agent_instances: Vec<AgentInstance>,
     pub properties: HashMap<u32, Property>,
     pub transactions: HashMap<u32, Vec<Transaction>>,   // Map property_id to transactions
-     pub market_data: HashMap<String, MarketData>,
+     pub market_data: HashMap<String, Vec<MarketData>>, // Map normalized area name to snapshots, oldest first
       pub opportunities: Vec<Opportunity>,
       pub last_analysis_time: u64,
+    pub analysis_cursors: HashMap<u32, u32>, // AgentId -> next property id of a sweep spanning several calls
+    pub pending_owners: HashMap<u32, Pubkey>, // AgentId -> owner named by TransferAgentOwnership, awaiting acceptance
 }
 
 // Define Instruction Enum
@@ -92,8 +223,26 @@ pub enum AgentInstruction {
     UpdateAgentInstanceStatus { agent_id: u32, instance_id: u32, status: u8 },
      RegisterProperty (Property),
     RecordTransaction {property_id: u32, transaction: Transaction},
-      UpdateMarketData { market_data: MarketData},
-    AnalyzeRealEstateOpportunities {agent_id: u32},
+      UpdateMarketData { agent_id: u32, market_data: MarketData}, // signed by the owner(s) of `agent_id`
+    AnalyzeRealEstateOpportunities {agent_id: u32, dry_run: bool},
+    Initialize { owner: Pubkey },
+    Heartbeat { instance_id: u32 },
+    GetStaleInstances { max_age: u64 },
+    GetAgentConfig { agent_id: u32 },
+    GetAgentInstance { instance_id: u32 },
+    ExportChecksum,
+    GetTriggeredInstances { agent_id: u32 },
+    GetCounterpartyStats { who: Pubkey },
+    TransferAgentOwnership { agent_id: u32, new_owner: Pubkey },
+    AcceptAgentOwnership { agent_id: u32 },
+    BatchRegisterProperties { properties: Vec<Property> },
+    GetAreaSummary { area: String },
+    GetOpportunitiesByType { opportunity_type: String, offset: u32, limit: u32 },
+    DeregisterProperty { property_id: u32 },
+    SetInstanceFilter { instance_id: u32, filter: Option<InstanceFilter> },
+    AckTriggeredOpportunity { instance_id: u32 },
+    GetStateStats,
+    CloseAgentInstance { instance_id: u32 },
 }
 
 // Entrypoint
@@ -111,20 +260,41 @@ pub fn process_instruction(
      let accounts_iter = &mut accounts.iter();
     let state_account = next_account_info(accounts_iter)?;
 
-    if !state_account.is_writable {
+    // Read-only queries don't write the state back and may pass it read-only
+    let read_only = matches!(
+        instruction,
+        AgentInstruction::ExportChecksum
+            | AgentInstruction::GetStateStats
+            | AgentInstruction::GetTriggeredInstances { .. }
+            | AgentInstruction::GetCounterpartyStats { .. }
+            | AgentInstruction::GetAreaSummary { .. }
+            | AgentInstruction::GetOpportunitiesByType { .. }
+            | AgentInstruction::GetAgentConfig { .. }
+            | AgentInstruction::GetAgentInstance { .. }
+            | AgentInstruction::GetStaleInstances { .. }
+    );
+    if !read_only && !state_account.is_writable {
         msg!("Program state account is not writeable");
         return Err(ProgramError::InvalidArgument);
     }
     
     // Load Program state (if available) or create a new one if not initialized
-    let mut program_state = ProgramState::try_from_slice(&state_account.data.borrow())
-         .unwrap_or_default();
+    let mut program_state = load_program_state(&state_account.data.borrow())?;
 
 
     match instruction {
+        AgentInstruction::Initialize { owner } => {
+            msg!("Initializing program state...");
+            initialize(&mut program_state, owner, program_id, state_account, accounts)?;
+        }
+        // Everything else needs an initialized state
+        _ if !program_state.is_initialized => {
+            msg!("Program state is not initialized");
+            return Err(ProgramError::UninitializedAccount);
+        }
         AgentInstruction::CreateAgent(config) => {
             msg!("Creating agent config...");
-            create_agent(&mut program_state, config, program_id, state_account)?;
+            create_agent(&mut program_state, config, program_id, state_account, accounts)?;
         }
         AgentInstruction::CreateAgentInstance { agent_id } => {
             msg!("Creating agent instance...");
@@ -134,6 +304,10 @@ pub fn process_instruction(
             msg!("Updating agent instance status...");
              update_agent_instance_status(&mut program_state, agent_id, instance_id, status, state_account)?;
         }
+        AgentInstruction::CloseAgentInstance { instance_id } => {
+            msg!("Closing agent instance...");
+            close_agent_instance(&mut program_state, instance_id, accounts)?;
+        }
         AgentInstruction::RegisterProperty (property) => {
             msg!("Registering new property...");
             register_property(&mut program_state, property, state_account)?;
@@ -142,14 +316,88 @@ pub fn process_instruction(
             msg!("Recording Transaction...");
            record_transaction(&mut program_state, property_id, transaction, state_account)?;
         }
-        AgentInstruction::UpdateMarketData{market_data} => {
+        AgentInstruction::UpdateMarketData{agent_id, market_data} => {
              msg!("Updating market data...");
-             update_market_data(&mut program_state, market_data, state_account)?;
+             update_market_data(&mut program_state, agent_id, market_data, accounts)?;
         }
-       AgentInstruction::AnalyzeRealEstateOpportunities { agent_id } => {
+       AgentInstruction::AnalyzeRealEstateOpportunities { agent_id, dry_run } => {
             msg!("Analyzing Real Estate opportunities...");
-            analyze_real_estate_opportunities(&mut program_state, agent_id, state_account)?;
+            analyze_real_estate_opportunities(&mut program_state, agent_id, dry_run, state_account)?;
        }
+        AgentInstruction::Heartbeat { instance_id } => {
+            msg!("Recording instance heartbeat...");
+            heartbeat(&mut program_state, instance_id, accounts)?;
+        }
+        AgentInstruction::GetStaleInstances { max_age } => {
+            msg!("Listing stale instances...");
+            get_stale_instances(&program_state, max_age)?;
+            return Ok(());
+        }
+        AgentInstruction::GetAgentConfig { agent_id } => {
+            msg!("Fetching agent config...");
+            get_agent_config(&program_state, agent_id)?;
+            return Ok(());
+        }
+        AgentInstruction::GetAgentInstance { instance_id } => {
+            msg!("Fetching agent instance...");
+            get_agent_instance(&program_state, instance_id)?;
+            return Ok(());
+        }
+        AgentInstruction::ExportChecksum => {
+            msg!("Exporting state checksum...");
+            export_checksum(&program_state)?;
+            return Ok(());
+        }
+        AgentInstruction::GetStateStats => {
+            msg!("Fetching state stats...");
+            get_state_stats(&program_state, state_account)?;
+            return Ok(());
+        }
+        AgentInstruction::GetTriggeredInstances { agent_id } => {
+            msg!("Fetching triggered instances...");
+            get_triggered_instances(&program_state, agent_id)?;
+            return Ok(());
+        }
+        AgentInstruction::GetCounterpartyStats { who } => {
+            msg!("Fetching counterparty stats...");
+            let stats = aggregate_counterparty_stats(&program_state.transactions).remove(&who).unwrap_or_default();
+            sol_log_data(&[&stats.try_to_vec()?]);
+            return Ok(());
+        }
+        AgentInstruction::TransferAgentOwnership { agent_id, new_owner } => {
+            msg!("Transferring agent ownership...");
+            transfer_agent_ownership(&mut program_state, agent_id, new_owner, accounts)?;
+        }
+        AgentInstruction::AcceptAgentOwnership { agent_id } => {
+            msg!("Accepting agent ownership...");
+            accept_agent_ownership(&mut program_state, agent_id, accounts)?;
+        }
+        AgentInstruction::BatchRegisterProperties { properties } => {
+            msg!("Registering property batch...");
+            batch_register_properties(&mut program_state, properties)?;
+        }
+        AgentInstruction::GetAreaSummary { area } => {
+            msg!("Fetching area summary...");
+            get_area_summary(&program_state, area)?;
+            return Ok(());
+        }
+        AgentInstruction::GetOpportunitiesByType { opportunity_type, offset, limit } => {
+            msg!("Listing opportunities by type...");
+            get_opportunities_by_type(&program_state, opportunity_type, offset, limit)?;
+            return Ok(());
+        }
+        AgentInstruction::DeregisterProperty { property_id } => {
+            msg!("Deregistering property...");
+            deregister_property(&mut program_state, property_id, accounts)?;
+        }
+        AgentInstruction::SetInstanceFilter { instance_id, filter } => {
+            msg!("Setting instance filter...");
+            set_instance_filter(&mut program_state, instance_id, filter, accounts)?;
+        }
+        AgentInstruction::AckTriggeredOpportunity { instance_id } => {
+            msg!("Acknowledging triggered opportunity...");
+            ack_triggered_opportunity(&mut program_state, instance_id, accounts)?;
+        }
     }
 
      // Serialize the program state back to the account
@@ -159,17 +407,63 @@ pub fn process_instruction(
 }
 
 // Instruction implementations
+// The owner must sign, it is the only key allowed to create agents afterwards
+fn initialize(
+    program_state: &mut ProgramState,
+    owner: Pubkey,
+    program_id: &Pubkey,
+    state_account: &AccountInfo,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    if state_account.owner != program_id {
+        msg!("Incorrect owner for program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if program_state.is_initialized {
+        msg!("Program state is already initialized");
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+    if !accounts.iter().any(|account| account.is_signer && *account.key == owner) {
+        msg!("Owner signature missing");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    program_state.is_initialized = true;
+    program_state.owner = owner;
+    msg!("Initialized program state with owner: {:?}", owner);
+    Ok(())
+}
+
 fn create_agent(
     program_state: &mut ProgramState,
     config: AgentConfig,
     program_id: &Pubkey,
      state_account: &AccountInfo,
+    accounts: &[AccountInfo],
 ) -> ProgramResult {
     // Check if the signer is the owner of program
      if state_account.owner != program_id {
         msg!("Incorrect owner for program");
         return Err(ProgramError::IncorrectProgramId);
     }
+    if !accounts.iter().any(|account| account.is_signer && *account.key == program_state.owner) {
+        msg!("Program owner signature missing");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if config.long_price_window > 0 {
+        let too_long = config.long_price_window as usize > MAX_MARKET_HISTORY_PER_AREA;
+        if config.short_price_window == 0 || config.short_price_window >= config.long_price_window || too_long {
+            msg!("Price windows must satisfy 0 < short < long <= {}", MAX_MARKET_HISTORY_PER_AREA);
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
+    if !config.owners.is_empty() {
+        let distinct = config.owners.iter().enumerate().all(|(index, owner)| !config.owners[..index].contains(owner));
+        if !distinct || config.owner_threshold == 0 || config.owner_threshold as usize > config.owners.len() {
+            msg!("Owner threshold must be between 1 and the number of distinct owners");
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
     
     let config_id = program_state.next_agent_id;
     program_state.agent_configs.push(config.clone());
@@ -191,14 +485,31 @@ fn create_agent_instance(
         return Err(ProgramError::InvalidArgument);
     }
 
+    let config = &program_state.agent_configs[agent_id as usize];
+    if config.max_concurrent_instances > 0 {
+        let active_instances = program_state.agent_instances.iter()
+            .filter(|instance| instance.agent_id == agent_id && instance.status <= 1)
+            .count();
+        if active_instances >= config.max_concurrent_instances as usize {
+            msg!("Agent {} already has {} of {} allowed instances created or running", agent_id, active_instances, config.max_concurrent_instances);
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
+
+    let start_time = solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64;
     let new_instance = AgentInstance {
+        id: program_state.next_instance_id,
         agent_id,
         status: 0, // Created status
-        start_time: solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64,
+        start_time,
+        last_heartbeat: start_time,
         triggered_opportunity: None,
+        filter: None,
+        acknowledged: false,
     };
 
      program_state.agent_instances.push(new_instance);
+     program_state.next_instance_id += 1;
      msg!("Created agent instance with agent ID: {}", agent_id);
     Ok(())
 }
@@ -210,12 +521,9 @@ fn update_agent_instance_status(
     status: u8,
     _state_account: &AccountInfo,
 ) -> ProgramResult {
-    if program_state.agent_instances.len() <= instance_id as usize {
-        msg!("Agent instance not found");
-        return Err(ProgramError::InvalidArgument);
-    }
+    let instance_index = find_instance_index(program_state, instance_id)?;
 
-     let instance = program_state.agent_instances.get_mut(instance_id as usize).unwrap();
+     let instance = &mut program_state.agent_instances[instance_index];
      if instance.agent_id != agent_id {
         msg!("Incorrect agent ID for the requested instance");
         return Err(ProgramError::InvalidArgument)
@@ -226,13 +534,317 @@ fn update_agent_instance_status(
      Ok(())
 }
 
+// Removes a completed or errored instance so the state account stops growing.
+// Instances live inside the state account today; if they move to their own
+// accounts this should also drain the instance account's lamports to the owner.
+fn close_agent_instance(
+    program_state: &mut ProgramState,
+    instance_id: u32,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let instance_index = find_instance_index(program_state, instance_id)?;
+    let instance = &program_state.agent_instances[instance_index];
+
+    let config = &program_state.agent_configs[instance.agent_id as usize];
+    verify_owner(config, accounts)?;
+
+    // Only completed (2) or errored (3) instances can be closed
+    if instance.status != 2 && instance.status != 3 {
+        msg!("Cannot close instance {} with status {}", instance_id, instance.status);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    program_state.agent_instances.remove(instance_index);
+    msg!("Closed agent instance with ID: {}", instance_id);
+    Ok(())
+}
+
+// First step of an ownership handoff: the current owner names the next owner,
+// who then has to accept. Naming another owner replaces a pending transfer.
+fn transfer_agent_ownership(
+    program_state: &mut ProgramState,
+    agent_id: u32,
+    new_owner: Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    // Check if agent exists
+    if program_state.agent_configs.len() <= agent_id as usize {
+        msg!("Agent not found");
+        return Err(ProgramError::InvalidArgument);
+    }
+    verify_owner(&program_state.agent_configs[agent_id as usize], accounts)?;
+
+    program_state.pending_owners.insert(agent_id, new_owner);
+    msg!("Agent {} ownership offered to {:?}", agent_id, new_owner);
+    Ok(())
+}
+
+// Second step: the pending owner signs to take over the agent
+fn accept_agent_ownership(
+    program_state: &mut ProgramState,
+    agent_id: u32,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let new_owner = match program_state.pending_owners.get(&agent_id) {
+        Some(new_owner) => *new_owner,
+        None => {
+            msg!("No ownership transfer pending for agent {}", agent_id);
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+    let new_owner_signed = accounts.iter().any(|account| account.is_signer && *account.key == new_owner);
+    if !new_owner_signed {
+        msg!("Pending owner signature missing");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // The new owner takes over alone, a multi-sig owner set is dissolved
+    let config = &mut program_state.agent_configs[agent_id as usize];
+    config.owner = new_owner;
+    config.owners.clear();
+    config.owner_threshold = 0;
+    program_state.pending_owners.remove(&agent_id);
+    msg!("Agent {} is now owned by {:?}", agent_id, new_owner);
+    Ok(())
+}
+
+// Records that the off-chain worker running an instance is still making progress.
+// The agent owner operates its instances, so the heartbeat must be signed by it.
+fn heartbeat(
+    program_state: &mut ProgramState,
+    instance_id: u32,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let instance_index = find_instance_index(program_state, instance_id)?;
+    let config = &program_state.agent_configs[program_state.agent_instances[instance_index].agent_id as usize];
+    verify_owner(config, accounts)?;
+
+    let current_time = solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64;
+    program_state.agent_instances[instance_index].last_heartbeat = current_time;
+    msg!("Heartbeat for instance {} at {}", instance_id, current_time);
+    Ok(())
+}
+
+// Marks a running instance's triggered opportunity as handled, so an agent with
+// `auto_complete_instances` completes the instance on its next analysis
+fn ack_triggered_opportunity(
+    program_state: &mut ProgramState,
+    instance_id: u32,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let instance_index = find_instance_index(program_state, instance_id)?;
+    let config = &program_state.agent_configs[program_state.agent_instances[instance_index].agent_id as usize];
+    verify_owner(config, accounts)?;
+
+    let instance = &mut program_state.agent_instances[instance_index];
+    if instance.status != 1 || instance.triggered_opportunity.is_none() {
+        msg!("Instance {} has no triggered opportunity to acknowledge", instance_id);
+        return Err(ProgramError::InvalidArgument);
+    }
+    instance.acknowledged = true;
+    msg!("Acknowledged the triggered opportunity of instance {}", instance_id);
+    Ok(())
+}
+
+// Restricts which opportunities trigger an instance, None clears the filter
+fn set_instance_filter(
+    program_state: &mut ProgramState,
+    instance_id: u32,
+    filter: Option<InstanceFilter>,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let instance_index = find_instance_index(program_state, instance_id)?;
+    let config = &program_state.agent_configs[program_state.agent_instances[instance_index].agent_id as usize];
+    verify_owner(config, accounts)?;
+
+    program_state.agent_instances[instance_index].filter = filter;
+    msg!("Updated filter of instance {}", instance_id);
+    Ok(())
+}
+
+fn filter_accepts(filter: &Option<InstanceFilter>, opportunity: &Opportunity) -> bool {
+    let filter = match filter {
+        Some(filter) => filter,
+        None => return true,
+    };
+    let type_matches = filter.opportunity_type.as_ref().map_or(true, |opportunity_type| *opportunity_type == opportunity.opportunity_type);
+    let roi_matches = filter.min_roi_bps.map_or(true, |min_roi_bps| opportunity.roi_bps.map_or(false, |roi_bps| roi_bps >= min_roi_bps));
+    type_matches && roi_matches
+}
+
+// Logs the ids of created or running instances without a heartbeat in the last `max_age` seconds
+fn get_stale_instances(program_state: &ProgramState, max_age: u64) -> ProgramResult {
+    let current_time = solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64;
+    let stale: Vec<u32> = program_state.agent_instances.iter()
+        .filter(|instance| instance.status < 2 && current_time.saturating_sub(instance.last_heartbeat) > max_age)
+        .map(|instance| instance.id)
+        .collect();
+
+    msg!("Found {} stale instances", stale.len());
+    sol_log_data(&[&stale.try_to_vec()?]);
+    Ok(())
+}
+
+fn get_agent_config(program_state: &ProgramState, agent_id: u32) -> ProgramResult {
+    let config = match program_state.agent_configs.get(agent_id as usize) {
+        Some(config) => config,
+        None => {
+            msg!("Agent not found");
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+    sol_log_data(&[&config.try_to_vec()?]);
+    Ok(())
+}
+
+fn get_agent_instance(program_state: &ProgramState, instance_id: u32) -> ProgramResult {
+    let instance_index = find_instance_index(program_state, instance_id)?;
+    sol_log_data(&[&program_state.agent_instances[instance_index].try_to_vec()?]);
+    Ok(())
+}
+
+// Logs the keccak hash of the serialized state and its length so an off-chain
+// mirror can check that it serializes to exactly the same bytes
+fn export_checksum(program_state: &ProgramState) -> ProgramResult {
+    let serialized = program_state.try_to_vec()?;
+    let digest = keccak::hash(&serialized);
+    msg!("State checksum {} over {} bytes", digest, serialized.len());
+    sol_log_data(&[&digest.to_bytes(), &(serialized.len() as u64).to_le_bytes()]);
+    Ok(())
+}
+
+// Logs entry counts and the serialized state size against the account's allocated
+// size, so operators can alert before the state outgrows its account
+fn get_state_stats(program_state: &ProgramState, state_account: &AccountInfo) -> ProgramResult {
+    let stats = StateStats {
+        agent_count: program_state.agent_configs.len() as u32,
+        instance_count: program_state.agent_instances.len() as u32,
+        property_count: program_state.properties.len() as u32,
+        transaction_count: program_state.transactions.values().map(|transactions| transactions.len()).sum::<usize>() as u32,
+        opportunity_count: program_state.opportunities.len() as u32,
+        market_data_count: program_state.market_data.values().map(|history| history.len()).sum::<usize>() as u32,
+        serialized_size: program_state.try_to_vec()?.len() as u64,
+        allocated_size: state_account.data_len() as u64,
+    };
+    msg!("State uses {} of {} allocated bytes", stats.serialized_size, stats.allocated_size);
+    sol_log_data(&[&stats.try_to_vec()?]);
+    Ok(())
+}
+
+// Logs (instance id, triggered opportunity) for every instance of the agent that an
+// analysis has triggered. An agent without triggered instances logs an empty list.
+fn get_triggered_instances(program_state: &ProgramState, agent_id: u32) -> ProgramResult {
+    // Check if agent exists
+    if program_state.agent_configs.len() <= agent_id as usize {
+        msg!("Agent not found");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let triggered: Vec<(u32, Opportunity)> = program_state.agent_instances.iter()
+        .filter(|instance| instance.agent_id == agent_id)
+        .filter_map(|instance| instance.triggered_opportunity.clone().map(|opportunity| (instance.id, opportunity)))
+        .collect();
+
+    msg!("Agent {} has {} triggered instances", agent_id, triggered.len());
+    sol_log_data(&[&triggered.try_to_vec()?]);
+    Ok(())
+}
+
+// Logs the recorded opportunities of one type, oldest first, skipping the first
+// `offset` matches and returning at most `limit` (capped at MAX_OPPORTUNITIES_PER_PAGE),
+// followed by the total number of matches. An unknown type yields an empty page.
+fn get_opportunities_by_type(program_state: &ProgramState, opportunity_type: String, offset: u32, limit: u32) -> ProgramResult {
+    let matching: Vec<&Opportunity> = program_state.opportunities.iter()
+        .filter(|opportunity| opportunity.opportunity_type == opportunity_type)
+        .collect();
+
+    let page: Vec<&Opportunity> = matching.iter()
+        .skip(offset as usize)
+        .take(limit.min(MAX_OPPORTUNITIES_PER_PAGE) as usize)
+        .copied()
+        .collect();
+
+    msg!("Returning {} of {} '{}' opportunities", page.len(), matching.len(), opportunity_type);
+    sol_log_data(&[&page.try_to_vec()?, &(matching.len() as u32).try_to_vec()?]);
+    Ok(())
+}
+
+// Sale and rental activity per counterparty. Buyers and sellers are credited with
+// sales, tenants with rentals.
+fn aggregate_counterparty_stats(transactions: &HashMap<u32, Vec<Transaction>>) -> HashMap<Pubkey, CounterpartyStats> {
+    let mut stats: HashMap<Pubkey, CounterpartyStats> = HashMap::new();
+    for transaction in transactions.values().flatten() {
+        if transaction.transaction_type == "Sale" {
+            for party in [transaction.buyer, transaction.seller].iter().flatten() {
+                let entry = stats.entry(*party).or_default();
+                entry.sale_count += 1;
+                entry.sale_volume = entry.sale_volume.saturating_add(transaction.price);
+            }
+        } else if transaction.transaction_type == "Rental" {
+            if let Some(tenant) = transaction.tenant {
+                let entry = stats.entry(tenant).or_default();
+                entry.rental_count += 1;
+                entry.rental_volume = entry.rental_volume.saturating_add(transaction.price);
+            }
+        }
+    }
+    stats
+}
+
+// Summarizes the properties whose address contains `area`, using their sales,
+// rentals and opportunities from the last AREA_SUMMARY_MAX_AGE seconds
+fn get_area_summary(program_state: &ProgramState, area: String) -> ProgramResult {
+    let current_time = solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64;
+    let is_recent = |timestamp: u64| current_time.saturating_sub(timestamp) <= AREA_SUMMARY_MAX_AGE;
+
+    let mut summary = AreaSummary::default();
+    let mut sale_samples: Vec<(f64, f64)> = Vec::new();
+    let mut rent_samples: Vec<f64> = Vec::new();
+    for property in program_state.properties.values().filter(|property| in_area(&property.address, &area)) {
+        summary.property_count += 1;
+        if property.size_sqft == 0 {
+            continue;
+        }
+        for transaction in program_state.transactions.get(&property.id).into_iter().flatten() {
+            if !is_recent(transaction.timestamp) {
+                continue;
+            }
+            let price_sqft = transaction.price as f64 / property.size_sqft as f64;
+            if transaction.transaction_type == "Sale" {
+                sale_samples.push((price_sqft, 1.0));
+            } else if transaction.transaction_type == "Rental" {
+                rent_samples.push(price_sqft);
+            }
+        }
+    }
+
+    summary.sale_count = sale_samples.len() as u32;
+    if !sale_samples.is_empty() {
+        summary.average_price_sqft = sale_samples.iter().map(|(price_sqft, _)| price_sqft).sum::<f64>() / sale_samples.len() as f64;
+        summary.median_price_sqft = weighted_median_price_sqft(&mut sale_samples).unwrap_or(0.0);
+    }
+    if !rent_samples.is_empty() {
+        summary.average_rent_sqft = rent_samples.iter().sum::<f64>() / rent_samples.len() as f64;
+    }
+    summary.opportunity_count = program_state.opportunities.iter()
+        .filter(|opportunity| is_recent(opportunity.timestamp))
+        .filter(|opportunity| program_state.properties.get(&opportunity.property_id).map_or(false, |property| in_area(&property.address, &area)))
+        .count() as u32;
+
+    msg!("Area {}: {} properties, {} recent sales", area, summary.property_count, summary.sale_count);
+    sol_log_data(&[&summary.try_to_vec()?]);
+    Ok(())
+}
+
 
 fn register_property(
     program_state: &mut ProgramState,
     mut property: Property,
      _state_account: &AccountInfo,
 ) -> ProgramResult {
+    validate_property(&property)?;
     property.id = program_state.next_property_id;
+    property.address = property.address.trim().to_string();
     program_state.properties.insert(property.id, property.clone());
      program_state.next_property_id += 1;
 
@@ -240,6 +852,87 @@ fn register_property(
     Ok(())
 }
 
+// Registers every property or none of them. Ids are assigned sequentially and the
+// assigned range is logged as (first id, last id).
+fn batch_register_properties(
+    program_state: &mut ProgramState,
+    properties: Vec<Property>,
+) -> ProgramResult {
+    if properties.is_empty() || properties.len() > MAX_PROPERTY_BATCH {
+        msg!("A batch holds 1 to {} properties, got {}", MAX_PROPERTY_BATCH, properties.len());
+        return Err(ProgramError::InvalidArgument);
+    }
+    for (index, property) in properties.iter().enumerate() {
+        if validate_property(property).is_err() {
+            msg!("Property {} of the batch is invalid", index);
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
+
+    let first_id = program_state.next_property_id;
+    for mut property in properties {
+        property.id = program_state.next_property_id;
+        property.address = property.address.trim().to_string();
+        program_state.properties.insert(property.id, property);
+        program_state.next_property_id += 1;
+    }
+    let last_id = program_state.next_property_id - 1;
+
+    msg!("Registered properties with IDs {} to {}", first_id, last_id);
+    sol_log_data(&[&first_id.to_le_bytes(), &last_id.to_le_bytes()]);
+    Ok(())
+}
+
+// Removes a property on its owner's signature. The property's transaction history is
+// pruned with it, opportunities already recorded for it are kept. next_property_id is
+// left untouched, so later registrations get fresh ids and can't pick up history
+// recorded against the deregistered one.
+fn deregister_property(
+    program_state: &mut ProgramState,
+    property_id: u32,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let property = match program_state.properties.get(&property_id) {
+        Some(property) => property,
+        None => {
+            msg!("Property not found");
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+    let owner_signed = accounts.iter().any(|account| account.is_signer && *account.key == property.owner);
+    if !owner_signed {
+        msg!("Property owner signature missing");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    program_state.properties.remove(&property_id);
+    let pruned = program_state.transactions.remove(&property_id).map_or(0, |transactions| transactions.len());
+    msg!("Deregistered property {}, pruned {} transactions", property_id, pruned);
+    Ok(())
+}
+
+// Area names are compared trimmed and lowercased wherever they meet: market data
+// keys, agent target areas and property addresses
+fn normalize_area(area: &str) -> String {
+    area.trim().to_lowercase()
+}
+
+fn in_area(address: &str, area: &str) -> bool {
+    normalize_area(address).contains(&normalize_area(area))
+}
+
+fn validate_property(property: &Property) -> ProgramResult {
+    if property.address.trim().is_empty() {
+        msg!("Property address is empty");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if property.size_sqft == 0 {
+        msg!("Property size must be positive");
+        return Err(ProgramError::InvalidArgument);
+    }
+    Ok(())
+}
+
 fn record_transaction(
     program_state: &mut ProgramState,
     property_id: u32,
@@ -259,19 +952,53 @@ fn record_transaction(
     Ok(())
 }
 
+// Snapshots are keyed by the normalized area name, so "Downtown" and "downtown "
+// feed the same series
 fn update_market_data(
      program_state: &mut ProgramState,
-      market_data: MarketData,
-     _state_account: &AccountInfo,
+    agent_id: u32,
+      mut market_data: MarketData,
+    accounts: &[AccountInfo],
 )->ProgramResult{
 
-      program_state.market_data.insert(market_data.area_name.clone(), market_data);
+    // Only the owner(s) of an agent may feed market data
+    let config = match program_state.agent_configs.get(agent_id as usize) {
+        Some(config) => config,
+        None => {
+            msg!("Agent not found");
+            return Err(ProgramError::InvalidArgument);
+        }
+    };
+    verify_owner(config, accounts)?;
+
+      market_data.area_name = normalize_area(&market_data.area_name);
+      let history = program_state.market_data.entry(market_data.area_name.clone()).or_insert_with(Vec::new);
+
+      // Keep each area's series ordered so trend checks can read it front to back
+      if let Some(latest) = history.last() {
+          if market_data.timestamp <= latest.timestamp {
+              msg!("Market data for {} must be newer than {}", market_data.area_name, latest.timestamp);
+              return Err(ProgramError::InvalidArgument);
+          }
+      }
+
+      history.push(market_data);
+      if history.len() > MAX_MARKET_HISTORY_PER_AREA {
+          history.remove(0);
+      }
         Ok(())
 }
 
+// With `dry_run` set the candidate opportunities are only logged via sol_log_data;
+// nothing is recorded, no instance is triggered and last_analysis_time is untouched.
+// Properties are checked in id order, at most `max_properties_per_analysis` per call,
+// so with a cap a full sweep may take several transactions. The agent's cursor
+// remembers where the next call resumes, the cooldown only applies when a new
+// sweep starts and last_analysis_time is set once a sweep completes.
 fn analyze_real_estate_opportunities(
     program_state: &mut ProgramState,
     agent_id: u32,
+    dry_run: bool,
     _state_account: &AccountInfo,
 ) -> ProgramResult {
 
@@ -283,57 +1010,188 @@ fn analyze_real_estate_opportunities(
 
      let config = &program_state.agent_configs[agent_id as usize];
 
+      let current_time = solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64;
+      let cursor = program_state.analysis_cursors.get(&agent_id).copied();
+      let next_allowed = program_state.last_analysis_time.saturating_add(config.analysis_cooldown);
+      if cursor.is_none() && current_time < next_allowed {
+          msg!("Analysis is on cooldown, retry in {} seconds", next_allowed - current_time);
+          return Err(ProgramError::InvalidArgument);
+      }
+
+      let mut property_ids: Vec<u32> = program_state.properties.keys().copied().filter(|id| *id >= cursor.unwrap_or(0)).collect();
+      property_ids.sort();
+      let sweep_complete = config.max_properties_per_analysis == 0 || property_ids.len() <= config.max_properties_per_analysis as usize;
+      if !sweep_complete {
+          property_ids.truncate(config.max_properties_per_analysis as usize);
+      }
+
     // Add the logic for identifying opportunities based on config
-      let opportunities = identify_real_estate_opportunities(config, &program_state.properties, &program_state.transactions, &program_state.market_data);
+      let opportunities = identify_real_estate_opportunities(config, &property_ids, cursor.is_none(), &program_state.properties, &program_state.transactions, &program_state.market_data, current_time);
+
+       if dry_run {
+           for opportunity in opportunities.iter() {
+               sol_log_data(&[&opportunity.try_to_vec()?]);
+           }
+           msg!("Dry run found {} opportunities", opportunities.len());
+           return Ok(());
+       }
+
+       // Complete instances whose triggered opportunity was handled or has gone stale,
+       // before this run triggers new ones
+       if config.auto_complete_instances {
+           let ttl = config.opportunity_ttl;
+           for instance in program_state.agent_instances.iter_mut() {
+               if instance.agent_id != agent_id || instance.status != 1 {
+                   continue;
+               }
+               let expired = match &instance.triggered_opportunity {
+                   Some(opportunity) => ttl > 0 && current_time >= opportunity.timestamp.saturating_add(ttl),
+                   None => false,
+               };
+               if instance.acknowledged || expired {
+                   msg!("Auto-completing instance {}", instance.id);
+                   instance.status = 2;
+               }
+           }
+       }
 
        for opportunity in opportunities {
            program_state.opportunities.push(opportunity.clone());
-            // Iterate through instances and trigger if applicable
+           let detected = OpportunityDetected {
+               agent_id,
+               property_id: opportunity.property_id,
+               opportunity_type: opportunity.opportunity_type.clone(),
+               roi_bps: opportunity.roi_bps,
+               timestamp: opportunity.timestamp,
+           };
+           sol_log_data(&[&detected.try_to_vec()?]);
+            // Iterate through instances and trigger those whose filter accepts the opportunity,
+            // the others stay created
             for instance in program_state.agent_instances.iter_mut() {
-                if instance.agent_id == agent_id && instance.status == 0 {
+                if instance.agent_id == agent_id && instance.status == 0 && filter_accepts(&instance.filter, &opportunity) {
                      msg!("Triggering instance {}", instance.agent_id);
                     instance.status = 1;
                     instance.triggered_opportunity = Some(opportunity.clone());
                 }
            }
       }
-      program_state.last_analysis_time =  solana_program::sysvar::clock::Clock::get().unwrap().unix_timestamp as u64;
+
+      if sweep_complete {
+          program_state.analysis_cursors.remove(&agent_id);
+          program_state.last_analysis_time = current_time;
+      } else {
+          let next_id = property_ids.last().map_or(0, |id| id + 1);
+          msg!("Analyzed {} properties, the sweep resumes at property {}", property_ids.len(), next_id);
+          program_state.analysis_cursors.insert(agent_id, next_id);
+      }
     Ok(())
 }
 
+// Checks the properties in `property_ids`. Area-wide checks run once per sweep,
+// on the call that starts it.
 fn identify_real_estate_opportunities(
     config: &AgentConfig,
+    property_ids: &[u32],
+    sweep_start: bool,
     properties: &HashMap<u32, Property>,
     transactions: &HashMap<u32, Vec<Transaction>>,
-    market_data: &HashMap<String, MarketData>
+    market_data: &HashMap<String, Vec<MarketData>>,
+    current_time: u64,
 ) -> Vec<Opportunity> {
      let mut opportunities = Vec::new();
 
        // Check if Market data exists for the area
-    let market_data_for_area = market_data.get(&config.target_area);
+    let area_key = normalize_area(&config.target_area);
+    let market_data_for_area = market_data.get(&area_key).and_then(|history| history.last());
     if market_data_for_area.is_none() {
         return opportunities; // No market data available for the area.
     }
      let market_data_area = market_data_for_area.unwrap();
 
-    // Iterate through all properties to perform analysis
-      for(property_id, property) in properties{
+    // A sparsely traded area gives meaningless results, skip it until it has enough activity.
+    // Counted over the whole area, not just this call's properties, so every call of a sweep agrees.
+    let area_transactions = properties.values()
+        .filter(|property| in_area(&property.address, &config.target_area))
+        .filter_map(|property| transactions.get(&property.id))
+        .flat_map(|history| history.iter())
+        .filter(|tx| is_recent_transaction(tx, config, current_time))
+        .count();
+    if area_transactions < config.min_transactions_per_area as usize {
+        msg!("Skipping {}: {} recent transactions, {} required", config.target_area, area_transactions, config.min_transactions_per_area);
+        return opportunities;
+    }
+
+    // Resolve the price per sqft used to value properties in the area
+    let price_sqft = match config.price_basis {
+        PriceBasis::MarketAverage => market_data_area.average_price_sqft,
+        PriceBasis::WeightedMedian => {
+            let mut samples: Vec<(f64, f64)> = properties.values()
+                .filter(|property| in_area(&property.address, &config.target_area) && property.size_sqft > 0)
+                .filter_map(|property| transactions.get(&property.id).map(|history| (property, history)))
+                .flat_map(|(property, history)| history.iter()
+                    .filter(|tx| tx.transaction_type == "Sale" && is_recent_transaction(tx, config, current_time))
+                    .map(move |tx| (tx.price as f64 / property.size_sqft as f64, property.size_sqft as f64)))
+                .collect();
+            // Fall back to the market average when the area has no recent sales
+            weighted_median_price_sqft(&mut samples).unwrap_or(market_data_area.average_price_sqft)
+        }
+    };
+
+    // Iterate through this call's properties to perform analysis
+      for (property_id, property) in property_ids.iter().filter_map(|id| properties.get_key_value(id)) {
              //Filter the properties based on the desired area.
-          if  !property.address.contains(&config.target_area) {
+          if  !in_area(&property.address, &config.target_area) {
                  continue;
           }
 
-        let opportunity = check_opportunity_condition(property_id, property, transactions, config, &market_data_area);
+        let opportunity = check_opportunity_condition(property_id, property, transactions, config, &market_data_area, price_sqft, current_time);
          if let Some(opportunity) = opportunity {
               opportunities.push(opportunity);
         }
     }
 
+    if sweep_start {
+        if let Some(opportunity) = cooling_market_condition(config, &market_data[&area_key], current_time) {
+            opportunities.push(opportunity);
+        }
+    }
+
     opportunities
 }
 
+// Flags the target area as "Cooling Market" when the SMA of its last `short_price_window`
+// average_price_sqft snapshots is below the SMA of the last `long_price_window`.
+// Areas with fewer snapshots than the long window are skipped.
+fn cooling_market_condition(config: &AgentConfig, history: &[MarketData], current_time: u64) -> Option<Opportunity> {
+    if config.long_price_window == 0 {
+        return None;
+    }
+    let prices: Vec<f64> = history.iter().map(|data| data.average_price_sqft).collect();
+    let short_sma = simple_moving_average(&prices, config.short_price_window as usize)?;
+    let long_sma = simple_moving_average(&prices, config.long_price_window as usize)?;
+    if short_sma >= long_sma {
+        return None;
+    }
+
+    Some(Opportunity {
+        property_id: AREA_OPPORTUNITY_ID,
+        opportunity_type: "Cooling Market".to_string(),
+        timestamp: current_time,
+        additional_info: format!("{}: SMA {} {:.2} below SMA {} {:.2}", config.target_area, config.short_price_window, short_sma, config.long_price_window, long_sma),
+        roi_bps: None,
+    })
+}
+
+// Mean of the last `period` values, None while the series is shorter than the period
+fn simple_moving_average(series: &[f64], period: usize) -> Option<f64> {
+    if period == 0 || series.len() < period {
+        return None;
+    }
+    Some(series[series.len() - period..].iter().sum::<f64>() / period as f64)
+}
 
-fn check_opportunity_condition(property_id: &u32, property: &Property, transactions: &HashMap<u32, Vec<Transaction>>, config: &AgentConfig, market_data: &MarketData) -> Option<Opportunity>{
+
+fn check_opportunity_condition(property_id: &u32, property: &Property, transactions: &HashMap<u32, Vec<Transaction>>, config: &AgentConfig, market_data: &MarketData, price_sqft: f64, current_time: u64) -> Option<Opportunity>{
          
           let transaction_history = transactions.get(property_id);
 
@@ -346,33 +1204,188 @@ fn check_opportunity_condition(property_id: &u32, property: &Property, transacti
           let latest_transaction = transaction_history_properties.iter().max_by_key(|tx| tx.timestamp);
         // Calculate the cap rate (example calculation using latest sale or rent)
         if let Some(latest_transaction) = latest_transaction {
-             if latest_transaction.transaction_type == "Rental" {
-                let cap_rate = calculate_cap_rate(market_data.average_price_sqft, market_data.average_rent_sqft);
-                   if cap_rate >= config.desired_cap_rate {
+             // Skip properties whose most recent activity is stale
+             if !is_recent_transaction(latest_transaction, config, current_time) {
+                 return None;
+             }
+
+             // Average every recent transaction of the same type so one-off prints carry less weight
+             let recent_prices: Vec<u64> = transaction_history_properties.iter()
+                 .filter(|tx| tx.transaction_type == latest_transaction.transaction_type && is_recent_transaction(tx, config, current_time))
+                 .map(|tx| tx.price)
+                 .collect();
+             let average_recent_price = average_price(&recent_prices);
+
+             // Composite mode needs every condition to hold for the same property
+             if config.composite_mode {
+                 let recent_sale_prices: Vec<u64> = transaction_history_properties.iter()
+                     .filter(|tx| tx.transaction_type == "Sale" && is_recent_transaction(tx, config, current_time))
+                     .map(|tx| tx.price)
+                     .collect();
+                 if recent_sale_prices.is_empty() {
+                     return None;
+                 }
+                 let average_sale_price = average_price(&recent_sale_prices);
+
+                 let rent_sqft = property_rent_sqft(config, property, transaction_history_properties, market_data, current_time)?;
+                 let cap_rate = cap_rate_condition(config, rent_sqft, price_sqft)?;
+                 let roi_bps = roi_condition(config, property, average_sale_price, price_sqft)?;
+                 let appreciation = appreciation_condition(config, transaction_history_properties)?;
+                 return Some(Opportunity {
+                     property_id: *property_id,
+                     opportunity_type: "Strong Buy".to_string(),
+                     timestamp: latest_transaction.timestamp,
+                     additional_info: format!("Cap Rate: {:.2}%, ROI: {} bps, Appreciation: {:.2}%", cap_rate * 100.0, roi_bps, appreciation * 100.0),
+                     roi_bps: Some(roi_bps),
+                 });
+             }
+
+             // With a rental window the averaged rentals decide, not whichever transaction came last
+             let cap_rate_applies = config.rental_window > 0 || latest_transaction.transaction_type == "Rental";
+             if config.check_cap_rate && cap_rate_applies {
+                   let cap_rate = property_rent_sqft(config, property, transaction_history_properties, market_data, current_time)
+                       .and_then(|rent_sqft| cap_rate_condition(config, rent_sqft, price_sqft));
+                   if let Some(cap_rate) = cap_rate {
                         return  Some(Opportunity {
                            property_id: *property_id,
                            opportunity_type: "High Cap Rate".to_string(),
                            timestamp: latest_transaction.timestamp,
                             additional_info: format!("Cap Rate: {:.2}%", cap_rate * 100.0),
+                           roi_bps: None,
+                         });
+                     }
+              }
+
+             if config.check_cash_yield && cap_rate_applies {
+                   let cash_yield = property_rent_sqft(config, property, transaction_history_properties, market_data, current_time)
+                       .and_then(|rent_sqft| cash_yield_condition(config, property, rent_sqft, price_sqft));
+                   if let Some(cash_yield) = cash_yield {
+                        return Some(Opportunity {
+                           property_id: *property_id,
+                           opportunity_type: "High Cash Yield".to_string(),
+                           timestamp: latest_transaction.timestamp,
+                           additional_info: format!("Cash-on-Cash: {:.2}%", cash_yield * 100.0),
+                           roi_bps: None,
                          });
                      }
               }
              
-               if latest_transaction.transaction_type == "Sale" {
-                   let roi = calculate_roi(latest_transaction.price as f64, market_data.average_price_sqft * property.size_sqft as f64);
-                      if roi >= config.min_roi {
+               if config.check_roi && latest_transaction.transaction_type == "Sale" {
+                      if let Some(roi_bps) = roi_condition(config, property, average_recent_price, price_sqft) {
                         return Some(Opportunity{
                            property_id: *property_id,
                            opportunity_type: "High ROI".to_string(),
                             timestamp: latest_transaction.timestamp,
-                           additional_info: format!("ROI: {:.2}%", roi * 100.0),
+                           additional_info: format!("ROI: {} bps", roi_bps),
+                           roi_bps: Some(roi_bps),
                          })
                        }
               }
+
+               if config.check_appreciation && latest_transaction.transaction_type == "Sale" {
+                       if let Some(appreciation) = appreciation_condition(config, transaction_history_properties) {
+                           return Some(Opportunity{
+                               property_id: *property_id,
+                               opportunity_type: "Price Appreciation".to_string(),
+                               timestamp: latest_transaction.timestamp,
+                               additional_info: format!("Appreciation: {:.2}%", appreciation * 100.0),
+                               roi_bps: None,
+                           })
+                       }
+              }
         }
       None
 }
 
+// Individual conditions, each returning its metric when it clears the configured threshold
+fn cap_rate_condition(config: &AgentConfig, rent_sqft: f64, price_sqft: f64) -> Option<f64> {
+    let cap_rate = calculate_cap_rate(price_sqft, rent_sqft);
+    if cap_rate >= config.desired_cap_rate { Some(cap_rate) } else { None }
+}
+
+// Rent per sqft used for the cap rate. With `rental_window` set this is the
+// recency-weighted average of the property's last K recent rentals (the newest
+// weighs K, the oldest 1) divided by its size. Sales are ignored, and a property
+// without rentals in the window yields None and is skipped.
+fn property_rent_sqft(config: &AgentConfig, property: &Property, transaction_history: &Vec<Transaction>, market_data: &MarketData, current_time: u64) -> Option<f64> {
+    if config.rental_window == 0 {
+        return Some(market_data.average_rent_sqft);
+    }
+    if property.size_sqft == 0 {
+        return None;
+    }
+
+    let mut rentals: Vec<&Transaction> = transaction_history.iter()
+        .filter(|tx| tx.transaction_type == "Rental" && is_recent_transaction(tx, config, current_time))
+        .collect();
+    rentals.sort_by_key(|tx| std::cmp::Reverse(tx.timestamp));
+    rentals.truncate(config.rental_window as usize);
+    if rentals.is_empty() {
+        return None;
+    }
+
+    let mut weighted_rent = 0.0;
+    let mut total_weight = 0.0;
+    for (age, rental) in rentals.iter().enumerate() {
+        let weight = (config.rental_window as usize - age) as f64;
+        weighted_rent += rental.price as f64 * weight;
+        total_weight += weight;
+    }
+    Some(weighted_rent / total_weight / property.size_sqft as f64)
+}
+
+fn roi_condition(config: &AgentConfig, property: &Property, sale_price: u64, price_sqft: f64) -> Option<i64> {
+    let roi_bps = calculate_roi_bps(sale_price, (price_sqft * property.size_sqft as f64) as u64);
+    if roi_bps >= config.min_roi_bps { Some(roi_bps) } else { None }
+}
+
+fn cash_yield_condition(config: &AgentConfig, property: &Property, rent_sqft: f64, price_sqft: f64) -> Option<f64> {
+    let size_sqft = property.size_sqft as f64;
+    let cash_yield = calculate_cash_on_cash(price_sqft * size_sqft, rent_sqft * size_sqft, property.financing.as_ref())?;
+    if cash_yield >= config.min_cash_yield { Some(cash_yield) } else { None }
+}
+
+// Integer mean of transaction prices, rounded down
+fn average_price(prices: &[u64]) -> u64 {
+    if prices.is_empty() {
+        return 0;
+    }
+    (prices.iter().map(|price| *price as u128).sum::<u128>() / prices.len() as u128) as u64
+}
+
+fn appreciation_condition(config: &AgentConfig, transaction_history: &Vec<Transaction>) -> Option<f64> {
+    let appreciation = calculate_appreciation(transaction_history)?;
+    if appreciation >= config.min_appreciation { Some(appreciation) } else { None }
+}
+
+fn is_recent_transaction(transaction: &Transaction, config: &AgentConfig, current_time: u64) -> bool {
+    config.max_transaction_age == 0 || current_time.saturating_sub(transaction.timestamp) <= config.max_transaction_age
+}
+
+// Weighted median of (price_sqft, weight) samples: the price at which half of the
+// total weight sits on either side. With an exact split (e.g. two equal weights)
+// the two middle prices are averaged. A single sample returns its own price.
+fn weighted_median_price_sqft(samples: &mut Vec<(f64, f64)>) -> Option<f64> {
+    samples.retain(|(_, weight)| *weight > 0.0);
+    if samples.is_empty() {
+        return None;
+    }
+    samples.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+
+    let total_weight: f64 = samples.iter().map(|(_, weight)| weight).sum();
+    let mut cumulative_weight = 0.0;
+    for (index, (price_sqft, weight)) in samples.iter().enumerate() {
+        cumulative_weight += weight;
+        if cumulative_weight * 2.0 == total_weight {
+            return Some(samples.get(index + 1).map_or(*price_sqft, |next| (price_sqft + next.0) / 2.0));
+        }
+        if cumulative_weight * 2.0 > total_weight {
+            return Some(*price_sqft);
+        }
+    }
+    None
+}
+
 // Example cap rate calculation
 fn calculate_cap_rate(average_price_sqft: f64, average_rent_sqft: f64) -> f64 {
     if average_price_sqft == 0.0 {
@@ -381,9 +1394,462 @@ fn calculate_cap_rate(average_price_sqft: f64, average_rent_sqft: f64) -> f64 {
     average_rent_sqft / average_price_sqft
 }
 
-fn calculate_roi(latest_sale_price: f64, purchase_price: f64 ) -> f64 {
-    if purchase_price == 0.0 {
-         return 0.0;
+// Cash-on-cash return = annual pre-tax cash flow / cash invested, where
+//   cash flow     = annual rent - annual debt service
+//   cash invested = purchase price - loan amount
+//   debt service  = 12 * L * r / (1 - (1 + r)^-n) for loan L, monthly rate r and n months (12 * L / n when r is 0)
+// Without financing, or with a zero loan, this is the unleveraged yield rent / price.
+// None when the loan covers the whole price or has no term.
+fn calculate_cash_on_cash(purchase_price: f64, annual_rent: f64, financing: Option<&Financing>) -> Option<f64> {
+    let financing = match financing {
+        Some(financing) if financing.loan_amount > 0 => financing,
+        _ => return if purchase_price > 0.0 { Some(annual_rent / purchase_price) } else { None },
+    };
+    let loan = financing.loan_amount as f64;
+    let cash_invested = purchase_price - loan;
+    if cash_invested <= 0.0 || financing.term_months == 0 {
+        return None;
+    }
+
+    let months = financing.term_months as f64;
+    let monthly_rate = financing.interest_rate_bps as f64 / 10_000.0 / 12.0;
+    let monthly_payment = if monthly_rate == 0.0 {
+        loan / months
+    } else {
+        loan * monthly_rate / (1.0 - (1.0 + monthly_rate).powf(-months))
+    };
+    Some((annual_rent - 12.0 * monthly_payment) / cash_invested)
+}
+
+// ROI in basis points using integer math only, so every validator computes the
+// same value. Rounds towards zero.
+fn calculate_roi_bps(latest_sale_price: u64, purchase_price: u64) -> i64 {
+    if purchase_price == 0 {
+         return 0;
+    }
+     ((latest_sale_price as i128 - purchase_price as i128) * 10_000 / purchase_price as i128) as i64
+}
+
+// Price growth between the two most recent sales of a property
+fn calculate_appreciation(transaction_history: &Vec<Transaction>) -> Option<f64> {
+    let mut sales: Vec<&Transaction> = transaction_history.iter().filter(|tx| tx.transaction_type == "Sale").collect();
+    if sales.len() < 2 {
+        return None;
+    }
+    sales.sort_by_key(|tx| tx.timestamp);
+
+    let latest_sale = sales[sales.len() - 1];
+    let previous_sale = sales[sales.len() - 2];
+    if previous_sale.price == 0 {
+        return None;
+    }
+    Some((latest_sale.price as f64 - previous_sale.price as f64) / previous_sale.price as f64)
+}
+
+fn find_instance_index(program_state: &ProgramState, instance_id: u32) -> Result<usize, ProgramError> {
+    match program_state.agent_instances.iter().position(|instance| instance.id == instance_id) {
+        Some(index) => Ok(index),
+        None => {
+            msg!("Agent instance not found");
+            Err(ProgramError::InvalidArgument)
+        }
+    }
+}
+
+// Check that the agent owner has signed the transaction, or for a multi-sig agent that
+// `owner_threshold` of its owners have
+fn verify_owner(config: &AgentConfig, accounts: &[AccountInfo]) -> ProgramResult {
+    if !config.owners.is_empty() {
+        let signed = config.owners.iter()
+            .filter(|owner| accounts.iter().any(|account| account.is_signer && account.key == *owner))
+            .count();
+        if signed < config.owner_threshold as usize {
+            msg!("{} of {} required owner signatures present", signed, config.owner_threshold);
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        return Ok(());
+    }
+    let owner_signed = accounts.iter().any(|account| account.is_signer && *account.key == config.owner);
+    if !owner_signed {
+        msg!("Agent owner signature missing");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(())
+}
+
+// Reads the state account. Only an all-zero account counts as uninitialized, data
+// that doesn't deserialize is an error instead of being replaced by a default state.
+fn load_program_state(data: &[u8]) -> Result<ProgramState, ProgramError> {
+    // A freshly allocated account is all zeroes
+    if data.iter().all(|byte| *byte == 0) {
+        return Ok(ProgramState { version: STATE_VERSION, ..ProgramState::default() });
+    }
+
+    // `deserialize` tolerates the zero padding after the serialized state
+    if data[0] == STATE_VERSION {
+        if let Ok(program_state) = ProgramState::deserialize(&mut &data[..]) {
+            return Ok(program_state);
+        }
+    }
+
+    // Accounts written before the version byte existed
+    if let Ok(legacy_state) = LegacyProgramState::deserialize(&mut &data[..]) {
+        msg!("Migrating program state to version {}", STATE_VERSION);
+        return Ok(legacy_state.into());
+    }
+
+    msg!("Unknown program state version: {}", data[0]);
+    Err(ProgramError::InvalidAccountData)
+}
+
+// Unversioned layouts, kept only to migrate existing accounts
+#[derive(BorshDeserialize)]
+struct LegacyProperty {
+    id: u32,
+    owner: Pubkey,
+    address: String,
+    size_sqft: u32,
+    features: Vec<String>,
+}
+
+#[derive(BorshDeserialize)]
+struct LegacyMarketData {
+    area_name: String,
+    average_price_sqft: f64,
+    average_rent_sqft: f64,
+}
+
+#[derive(BorshDeserialize)]
+struct LegacyOpportunity {
+    property_id: u32,
+    opportunity_type: String,
+    timestamp: u64,
+    additional_info: String,
+}
+
+#[derive(BorshDeserialize)]
+struct LegacyAgentConfig {
+    owner: Pubkey,
+    description: String,
+    target_area: String,
+    desired_cap_rate: f64,
+    min_roi: f64, // fraction, eg: 0.15 for 15%
+}
+
+#[derive(BorshDeserialize)]
+struct LegacyAgentInstance {
+    agent_id: u32,
+    status: u8,
+    start_time: u64,
+    triggered_opportunity: Option<LegacyOpportunity>,
+}
+
+#[derive(BorshDeserialize)]
+struct LegacyProgramState {
+    next_agent_id: u32,
+    next_property_id: u32,
+    agent_configs: Vec<LegacyAgentConfig>,
+    agent_instances: Vec<LegacyAgentInstance>,
+    properties: HashMap<u32, LegacyProperty>,
+    transactions: HashMap<u32, Vec<Transaction>>,
+    market_data: HashMap<String, LegacyMarketData>,
+    opportunities: Vec<LegacyOpportunity>,
+    last_analysis_time: u64,
+}
+
+impl From<LegacyOpportunity> for Opportunity {
+    fn from(legacy: LegacyOpportunity) -> Self {
+        Opportunity {
+            property_id: legacy.property_id,
+            opportunity_type: legacy.opportunity_type,
+            timestamp: legacy.timestamp,
+            additional_info: legacy.additional_info,
+            roi_bps: None,
+        }
+    }
+}
+
+impl From<LegacyProgramState> for ProgramState {
+    fn from(legacy: LegacyProgramState) -> Self {
+        // Legacy agents only checked cap rate and ROI
+        let agent_configs: Vec<AgentConfig> = legacy.agent_configs.into_iter().map(|config| AgentConfig {
+            owner: config.owner,
+            description: config.description,
+            target_area: config.target_area,
+            desired_cap_rate: config.desired_cap_rate,
+            min_roi_bps: (config.min_roi * 10_000.0).round() as i64,
+            min_appreciation: 0.0,
+            check_cap_rate: true,
+            check_roi: true,
+            check_appreciation: false,
+            max_transaction_age: 0,
+            price_basis: PriceBasis::default(),
+            analysis_cooldown: 0,
+            composite_mode: false,
+            rental_window: 0,
+            short_price_window: 0,
+            long_price_window: 0,
+            max_properties_per_analysis: 0,
+            check_cash_yield: false,
+            min_cash_yield: 0.0,
+            owners: Vec::new(),
+            owner_threshold: 0,
+            max_concurrent_instances: 0,
+            min_transactions_per_area: 0,
+            auto_complete_instances: false,
+            opportunity_ttl: 0,
+        }).collect();
+
+        // Legacy instance ids were their position in the vector
+        let agent_instances: Vec<AgentInstance> = legacy.agent_instances.into_iter().enumerate().map(|(index, instance)| AgentInstance {
+            id: index as u32,
+            agent_id: instance.agent_id,
+            status: instance.status,
+            start_time: instance.start_time,
+            last_heartbeat: instance.start_time,
+            triggered_opportunity: instance.triggered_opportunity.map(Opportunity::from),
+            filter: None,
+            acknowledged: false,
+        }).collect();
+
+        let properties = legacy.properties.into_iter().map(|(id, property)| (id, Property {
+            id: property.id,
+            owner: property.owner,
+            address: property.address,
+            size_sqft: property.size_sqft,
+            features: property.features,
+            financing: None,
+        })).collect();
+
+        // Each area kept a single snapshot, it becomes the first entry of its history.
+        // Its time is unknown, timestamp 0 makes it the oldest.
+        let market_data = legacy.market_data.into_iter().map(|(area, data)| (normalize_area(&area), vec![MarketData {
+            area_name: data.area_name,
+            average_price_sqft: data.average_price_sqft,
+            average_rent_sqft: data.average_rent_sqft,
+            timestamp: 0,
+        }])).collect();
+
+        // Legacy accounts were in use already, the first agent's owner becomes the state owner
+        let owner = agent_configs.first().map(|config| config.owner).unwrap_or_default();
+
+        ProgramState {
+            version: STATE_VERSION,
+            is_initialized: true,
+            owner,
+            next_agent_id: legacy.next_agent_id,
+            next_instance_id: agent_instances.len() as u32,
+            next_property_id: legacy.next_property_id,
+            agent_configs,
+            agent_instances,
+            properties,
+            transactions: legacy.transactions,
+            market_data,
+            opportunities: legacy.opportunities.into_iter().map(Opportunity::from).collect(),
+            last_analysis_time: legacy.last_analysis_time,
+            analysis_cursors: HashMap::new(),
+            pending_owners: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "12 Main St, Springfield";
+
+    // Every strategy enabled, as for an agent created before the toggles existed
+    fn config() -> AgentConfig {
+        AgentConfig {
+            owner: Pubkey::new_unique(),
+            description: String::new(),
+            target_area: "Springfield".to_string(),
+            desired_cap_rate: 0.05,
+            min_roi_bps: 1500,
+            min_appreciation: 0.1,
+            check_cap_rate: true,
+            check_roi: true,
+            check_appreciation: true,
+            max_transaction_age: 0,
+            price_basis: PriceBasis::MarketAverage,
+            analysis_cooldown: 0,
+            composite_mode: false,
+            rental_window: 0,
+            short_price_window: 0,
+            long_price_window: 0,
+            max_properties_per_analysis: 0,
+            check_cash_yield: false,
+            min_cash_yield: 0.0,
+            owners: Vec::new(),
+            owner_threshold: 0,
+            max_concurrent_instances: 0,
+            min_transactions_per_area: 0,
+            auto_complete_instances: false,
+            opportunity_ttl: 0,
+        }
+    }
+
+    fn property(id: u32) -> Property {
+        Property {
+            id,
+            owner: Pubkey::new_unique(),
+            address: ADDRESS.to_string(),
+            size_sqft: 1_000,
+            features: Vec::new(),
+            financing: None,
+        }
+    }
+
+    fn transaction(property_id: u32, transaction_type: &str, price: u64, timestamp: u64) -> Transaction {
+        Transaction {
+            property_id,
+            transaction_type: transaction_type.to_string(),
+            price,
+            timestamp,
+            ..Transaction::default()
+        }
+    }
+
+    // 100 per sqft to buy and 10 per sqft to rent, a 10% cap rate
+    fn market_data() -> MarketData {
+        MarketData {
+            area_name: "Springfield".to_string(),
+            average_price_sqft: 100.0,
+            average_rent_sqft: 10.0,
+            timestamp: 0,
+        }
+    }
+
+    fn opportunity_type(config: &AgentConfig, history: Vec<Transaction>) -> Option<String> {
+        let transactions = HashMap::from([(0, history)]);
+        check_opportunity_condition(&0, &property(0), &transactions, config, &market_data(), 100.0, 1_000)
+            .map(|opportunity| opportunity.opportunity_type)
+    }
+
+    #[test]
+    fn disabled_cap_rate_produces_no_opportunity() {
+        let rental = vec![transaction(0, "Rental", 10_000, 100)];
+        assert_eq!(opportunity_type(&config(), rental.clone()).as_deref(), Some("High Cap Rate"));
+
+        let config = AgentConfig { check_cap_rate: false, ..config() };
+        assert_eq!(opportunity_type(&config, rental), None);
+    }
+
+    #[test]
+    fn disabled_roi_produces_no_opportunity() {
+        // Sold for 150,000 against a 100,000 market value, 5000 bps
+        let sale = vec![transaction(0, "Sale", 150_000, 100)];
+        assert_eq!(opportunity_type(&config(), sale.clone()).as_deref(), Some("High ROI"));
+
+        let config = AgentConfig { check_roi: false, ..config() };
+        assert_eq!(opportunity_type(&config, sale), None);
+    }
+
+    #[test]
+    fn disabled_appreciation_produces_no_opportunity() {
+        // 20% growth between two sales, both below the market value so ROI doesn't qualify
+        let sales = vec![transaction(0, "Sale", 50_000, 100), transaction(0, "Sale", 60_000, 200)];
+        assert_eq!(opportunity_type(&config(), sales.clone()).as_deref(), Some("Price Appreciation"));
+
+        let config = AgentConfig { check_appreciation: false, ..config() };
+        assert_eq!(opportunity_type(&config, sales), None);
+    }
+
+    #[test]
+    fn weighted_median_averages_an_even_split() {
+        let mut samples = vec![(400.0, 1.0), (100.0, 1.0), (300.0, 1.0), (200.0, 1.0)];
+        assert_eq!(weighted_median_price_sqft(&mut samples), Some(250.0));
+    }
+
+    #[test]
+    fn weighted_median_of_a_single_sale_is_its_price() {
+        let mut samples = vec![(120.0, 800.0)];
+        assert_eq!(weighted_median_price_sqft(&mut samples), Some(120.0));
+    }
+
+    #[test]
+    fn weighted_median_follows_the_weight() {
+        // The large property outweighs the two small ones, an outlier price doesn't move it
+        let mut samples = vec![(100.0, 500.0), (150.0, 2_000.0), (900.0, 500.0)];
+        assert_eq!(weighted_median_price_sqft(&mut samples), Some(150.0));
+        assert_eq!(weighted_median_price_sqft(&mut Vec::new()), None);
+    }
+
+    #[test]
+    fn all_cash_purchase_falls_back_to_unleveraged_yield() {
+        assert_eq!(calculate_cash_on_cash(100_000.0, 8_000.0, None), Some(0.08));
+        let no_loan = Financing { loan_amount: 0, interest_rate_bps: 650, term_months: 360 };
+        assert_eq!(calculate_cash_on_cash(100_000.0, 8_000.0, Some(&no_loan)), Some(0.08));
+    }
+
+    #[test]
+    fn leverage_changes_the_cash_on_cash_return() {
+        // Interest free 80,000 over 20 years costs 4,000 a year, leaving 4,000 on 20,000 invested
+        let interest_free = Financing { loan_amount: 80_000, interest_rate_bps: 0, term_months: 240 };
+        let leveraged = calculate_cash_on_cash(100_000.0, 8_000.0, Some(&interest_free)).unwrap();
+        assert!((leveraged - 0.2).abs() < 1e-9);
+
+        // At 6.5% over 30 years the debt service is about 6,068 a year
+        let mortgage = Financing { loan_amount: 80_000, interest_rate_bps: 650, term_months: 360 };
+        let leveraged = calculate_cash_on_cash(100_000.0, 8_000.0, Some(&mortgage)).unwrap();
+        assert!((leveraged - 0.0966).abs() < 1e-3);
+
+        // A loan covering the whole price leaves no cash invested
+        let full_loan = Financing { loan_amount: 100_000, interest_rate_bps: 650, term_months: 360 };
+        assert_eq!(calculate_cash_on_cash(100_000.0, 8_000.0, Some(&full_loan)), None);
+    }
+
+    #[test]
+    fn high_cash_yield_uses_the_property_financing() {
+        let config = AgentConfig { check_cap_rate: false, check_cash_yield: true, min_cash_yield: 0.15, ..config() };
+        let transactions = HashMap::from([(0, vec![transaction(0, "Rental", 8_000, 100)])]);
+        let market_data = MarketData { average_rent_sqft: 8.0, ..market_data() };
+
+        // Unleveraged the yield is 8%, below the threshold
+        let unleveraged = check_opportunity_condition(&0, &property(0), &transactions, &config, &market_data, 100.0, 1_000);
+        assert!(unleveraged.is_none());
+
+        let financed = Property {
+            financing: Some(Financing { loan_amount: 80_000, interest_rate_bps: 0, term_months: 240 }),
+            ..property(0)
+        };
+        let leveraged = check_opportunity_condition(&0, &financed, &transactions, &config, &market_data, 100.0, 1_000);
+        assert_eq!(leveraged.map(|opportunity| opportunity.opportunity_type).as_deref(), Some("High Cash Yield"));
+    }
+
+    #[test]
+    fn registering_after_a_deregistration_gets_a_fresh_id() {
+        let mut program_state = ProgramState::default();
+        let (state_key, program_id) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (mut lamports, mut data) = (0, Vec::<u8>::new());
+        let state_account = AccountInfo::new(&state_key, false, true, &mut lamports, &mut data, &program_id, false, 0);
+
+        register_property(&mut program_state, property(0), &state_account).unwrap();
+        let owner = Pubkey::new_unique();
+        register_property(&mut program_state, Property { owner, ..property(0) }, &state_account).unwrap();
+        program_state.transactions.insert(1, vec![transaction(1, "Sale", 100_000, 100)]);
+
+        let (mut owner_lamports, mut owner_data) = (0, Vec::<u8>::new());
+        let owner_account = AccountInfo::new(&owner, true, false, &mut owner_lamports, &mut owner_data, &program_id, false, 0);
+        deregister_property(&mut program_state, 1, &[state_account.clone(), owner_account]).unwrap();
+        assert!(!program_state.transactions.contains_key(&1));
+
+        register_property(&mut program_state, property(0), &state_account).unwrap();
+        assert_eq!(program_state.next_property_id, 3);
+        assert!(program_state.properties.contains_key(&2));
+        assert!(!program_state.properties.contains_key(&1));
+        assert!(!program_state.transactions.contains_key(&2));
+    }
+
+    #[test]
+    fn area_below_min_transactions_produces_no_opportunities() {
+        let properties = HashMap::from([(0, property(0))]);
+        let transactions = HashMap::from([(0, vec![transaction(0, "Rental", 10_000, 100)])]);
+        let market_data = HashMap::from([(normalize_area("Springfield"), vec![market_data()])]);
+        let analyze = |config: &AgentConfig| identify_real_estate_opportunities(config, &[0], true, &properties, &transactions, &market_data, 1_000);
+
+        assert_eq!(analyze(&AgentConfig { min_transactions_per_area: 1, ..config() }).len(), 1);
+        assert!(analyze(&AgentConfig { min_transactions_per_area: 2, ..config() }).is_empty());
     }
-     (latest_sale_price - purchase_price) / purchase_price
 }
\ No newline at end of file